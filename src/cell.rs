@@ -0,0 +1,214 @@
+use std::{fmt, str::FromStr};
+
+/// The width of a memory cell.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CellSize {
+    /// 8 bit cells, as per brainfuck "spec"
+    #[default]
+    Bits8,
+    Bits16,
+    Bits32,
+    Bits64,
+}
+
+/// What happens when ``+`` or ``-`` would move a cell past it's maximum or minimum value.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ArithmeticMode {
+    /// The cell wraps around, 255 + 1 is 0 for 8 bit cells.
+    #[default]
+    Wrapping,
+    /// The cell stays at it's maximum or minimum value.
+    Saturating,
+    /// The program is aborted with a runtime error.
+    Trapping,
+}
+
+/// A memory cell of the VM, implemented for ``u8``, ``u16``, ``u32`` and ``u64``.
+/// Signed cells are stored as their two's complement, so ``[`` and ``]`` test for zero the same way.
+/// ``.`` writes the lowest 8 bits of a cell and ``,`` stores a byte, sign extended for signed cells.
+pub(crate) trait Cell: Copy + Default + Eq + fmt::Debug {
+    /// All bits set, -1 for signed cells and the maximum value for unsigned ones.
+    const MINUS_ONE: Self;
+
+    fn from_byte(byte: u8, signed: bool) -> Self;
+
+    fn to_byte(self) -> u8;
+
+    /// Truncates ``value`` to the cell size, like wrapping arithmetic would.
+    fn from_value(value: i64) -> Self;
+
+    /// The value of the cell, negative if it's signed and the highest bit is set.
+    fn value(self, signed: bool) -> i128;
+
+    /// Adds ``value * factor`` to the cell, always wrapping around.
+    fn mul_add(self, value: Self, factor: i32) -> Self;
+
+    /// Adds ``n`` to the cell, or subtracts it if it's negative, always wrapping around.
+    fn wrapping_add_signed(self, n: i32) -> Self;
+
+    /// Adds ``n`` to the cell, returns ``None`` if it overflows in [`ArithmeticMode::Trapping`].
+    fn add(self, n: u32, mode: ArithmeticMode, signed: bool) -> Option<Self>;
+
+    /// Subtracts ``n`` from the cell, returns ``None`` if it underflows in [`ArithmeticMode::Trapping`].
+    fn sub(self, n: u32, mode: ArithmeticMode, signed: bool) -> Option<Self>;
+}
+
+macro_rules! impl_cell {
+    ($($t:ty => $s:ty),*) => {
+        $(
+            impl Cell for $t {
+                const MINUS_ONE: Self = <$t>::MAX;
+
+                #[inline]
+                fn from_byte(byte: u8, signed: bool) -> Self {
+                    if signed {
+                        byte as i8 as $s as $t
+                    } else {
+                        byte as $t
+                    }
+                }
+
+                #[inline]
+                fn to_byte(self) -> u8 {
+                    self as u8
+                }
+
+                #[inline]
+                fn from_value(value: i64) -> Self {
+                    value as $t
+                }
+
+                #[inline]
+                fn value(self, signed: bool) -> i128 {
+                    if signed {
+                        self as $s as i128
+                    } else {
+                        self as i128
+                    }
+                }
+
+                #[inline]
+                fn mul_add(self, value: Self, factor: i32) -> Self {
+                    self.wrapping_add(value.wrapping_mul(factor as i64 as $t))
+                }
+
+                #[inline]
+                fn wrapping_add_signed(self, n: i32) -> Self {
+                    self.wrapping_add(n as i64 as $t)
+                }
+
+                #[inline]
+                fn add(self, n: u32, mode: ArithmeticMode, signed: bool) -> Option<Self> {
+                    let Some(m) = <$t>::try_from(n).ok() else {
+                        // adding more than the cell can hold always goes past the limit
+                        return match mode {
+                            ArithmeticMode::Wrapping => Some(self.wrapping_add(n as $t)),
+                            ArithmeticMode::Saturating if signed => Some(<$s>::MAX as $t),
+                            ArithmeticMode::Saturating => Some(<$t>::MAX),
+                            ArithmeticMode::Trapping => None,
+                        };
+                    };
+
+                    match (mode, signed) {
+                        (ArithmeticMode::Wrapping, _) => Some(self.wrapping_add(m)),
+                        (ArithmeticMode::Saturating, false) => Some(self.saturating_add(m)),
+                        (ArithmeticMode::Saturating, true) => {
+                            Some((self as $s).saturating_add_unsigned(m) as $t)
+                        }
+                        (ArithmeticMode::Trapping, false) => self.checked_add(m),
+                        (ArithmeticMode::Trapping, true) => {
+                            (self as $s).checked_add_unsigned(m).map(|v| v as $t)
+                        }
+                    }
+                }
+
+                #[inline]
+                fn sub(self, n: u32, mode: ArithmeticMode, signed: bool) -> Option<Self> {
+                    let Some(m) = <$t>::try_from(n).ok() else {
+                        // subtracting more than the cell can hold always goes past the limit
+                        return match mode {
+                            ArithmeticMode::Wrapping => Some(self.wrapping_sub(n as $t)),
+                            ArithmeticMode::Saturating if signed => Some(<$s>::MIN as $t),
+                            ArithmeticMode::Saturating => Some(0),
+                            ArithmeticMode::Trapping => None,
+                        };
+                    };
+
+                    match (mode, signed) {
+                        (ArithmeticMode::Wrapping, _) => Some(self.wrapping_sub(m)),
+                        (ArithmeticMode::Saturating, false) => Some(self.saturating_sub(m)),
+                        (ArithmeticMode::Saturating, true) => {
+                            Some((self as $s).saturating_sub_unsigned(m) as $t)
+                        }
+                        (ArithmeticMode::Trapping, false) => self.checked_sub(m),
+                        (ArithmeticMode::Trapping, true) => {
+                            (self as $s).checked_sub_unsigned(m).map(|v| v as $t)
+                        }
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_cell!(u8 => i8, u16 => i16, u32 => i32, u64 => i64);
+
+impl CellSize {
+    pub fn bits(&self) -> u32 {
+        match self {
+            CellSize::Bits8 => 8,
+            CellSize::Bits16 => 16,
+            CellSize::Bits32 => 32,
+            CellSize::Bits64 => 64,
+        }
+    }
+}
+
+impl FromStr for CellSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "8" => Ok(CellSize::Bits8),
+            "16" => Ok(CellSize::Bits16),
+            "32" => Ok(CellSize::Bits32),
+            "64" => Ok(CellSize::Bits64),
+            _ => Err(format!(
+                "unsupported cell size '{}', expected 8, 16, 32 or 64",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for CellSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.bits())
+    }
+}
+
+impl FromStr for ArithmeticMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "wrapping" => Ok(ArithmeticMode::Wrapping),
+            "saturating" => Ok(ArithmeticMode::Saturating),
+            "trapping" => Ok(ArithmeticMode::Trapping),
+            _ => Err(format!(
+                "unknown arithmetic mode '{}', expected wrapping, saturating or trapping",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ArithmeticMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArithmeticMode::Wrapping => write!(f, "wrapping"),
+            ArithmeticMode::Saturating => write!(f, "saturating"),
+            ArithmeticMode::Trapping => write!(f, "trapping"),
+        }
+    }
+}
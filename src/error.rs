@@ -0,0 +1,242 @@
+use std::{error::Error, fmt, io};
+
+use crate::source::Snippet;
+
+/// Errors that can occur while loading or running a brainfuck program.
+#[derive(Debug)]
+pub enum BrainfuckError {
+    /// Reading the program, reading input or writing output failed.
+    Io(io::Error),
+    /// The program could not be parsed.
+    Parse(ParseError),
+    /// The program did something it shouldn't while running.
+    Runtime(Box<RuntimeError>),
+    /// The VM ended up in a state that should be impossible. Please report this error.
+    Internal(&'static str),
+}
+
+/// Errors that can occur while parsing a brainfuck program, one [`Diagnostic`] for each problem.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// A single problem in the source code of a program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    /// Where the problem is
+    pub snippet: Snippet,
+    /// For an unclosed ``[``: the innermost ``[`` that is still open at the end of the program,
+    /// if that isn't this bracket itself
+    pub innermost: Option<Snippet>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    /// A ``]`` without a matching ``[``
+    UnmatchedClosingBracket,
+    /// A ``[`` without a matching ``]``
+    UnclosedBracket,
+}
+
+/// An error that occured while running a brainfuck program,
+/// together with a "backtrace" of the loops it occured in and the memory around the memory pointer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuntimeError {
+    pub kind: RuntimeErrorKind,
+    /// ProgramPointer of the failing instruction
+    pub pp: usize,
+    /// MemoryPointer when the error occured, relative to the starting cell
+    pub mp: isize,
+    /// Where the failing instruction is in the source code
+    pub location: Option<Snippet>,
+    /// The loops the failing instruction is in, innermost first
+    pub backtrace: Vec<LoopFrame>,
+    /// The cells around the memory pointer
+    pub memory: MemoryWindow,
+}
+
+/// A loop that was running when a [`RuntimeError`] occured.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoopFrame {
+    /// ProgramPointer of the loop's ``[``
+    pub pp: usize,
+    /// Where the loop's ``[`` is in the source code
+    pub location: Option<Snippet>,
+    /// The iteration the loop was in, starting at 1
+    pub iteration: u64,
+}
+
+/// A copy of the cells ``start..start + cells.len()``, relative to the starting cell.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryWindow {
+    pub start: isize,
+    /// The values of the cells, negative values only occur with signed cells
+    pub cells: Vec<i128>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuntimeErrorKind {
+    /// The memory pointer was moved left of the first cell.
+    MemoryUnderflow,
+    /// The memory pointer was moved right of the last cell and the memory can't grow any further.
+    OutOfMemory(usize),
+    /// ``,`` was executed but there was no input left.
+    UnexpectedEof,
+    /// The program executed more instructions than allowed.
+    StepLimitExceeded(u64),
+    /// ``+`` would increment a cell past it's maximum value in trapping arithmetic mode.
+    CellOverflow,
+    /// ``-`` would decrement a cell below it's minimum value in trapping arithmetic mode.
+    CellUnderflow,
+}
+
+impl fmt::Display for BrainfuckError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BrainfuckError::Io(e) => write!(f, "I/O error: {}", e),
+            BrainfuckError::Parse(e) => write!(f, "{}", e),
+            BrainfuckError::Runtime(e) => write!(f, "{}", e),
+            BrainfuckError::Internal(e) => {
+                write!(f, "internal error: {} (please report this error)", e)
+            }
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, diagnostic) in self.diagnostics.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+                writeln!(f)?;
+            }
+            write!(f, "{}", diagnostic)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            DiagnosticKind::UnmatchedClosingBracket => {
+                writeln!(f, "error: unmatched closing bracket `]`")?;
+                self.snippet.render(f, "no matching `[` before this")?;
+            }
+            DiagnosticKind::UnclosedBracket => {
+                writeln!(f, "error: unclosed bracket `[`")?;
+                self.snippet.render(f, "this `[` is never closed")?;
+            }
+        }
+
+        if let Some(innermost) = &self.innermost {
+            writeln!(f)?;
+            writeln!(f, "note: the innermost unclosed `[` is here")?;
+            innermost.render(f, "")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "error: {}", self.kind)?;
+        match &self.location {
+            Some(location) => location.render(f, self.kind.label())?,
+            None => write!(f, " --> instruction {}", self.pp)?,
+        }
+
+        if !self.backtrace.is_empty() {
+            writeln!(f)?;
+            write!(f, "loop backtrace (innermost first):")?;
+            for (i, frame) in self.backtrace.iter().enumerate() {
+                writeln!(f)?;
+                write!(f, "  {:>2}: ", i)?;
+                match &frame.location {
+                    Some(l) => write!(f, "[ at {}:{}:{}", l.name, l.line, l.column)?,
+                    None => write!(f, "[ at instruction {}", frame.pp)?,
+                }
+                write!(f, ", iteration {}", frame.iteration)?;
+            }
+        }
+
+        writeln!(f)?;
+        write!(f, "memory pointer: {}", self.mp)?;
+        for (i, cell) in self.memory.cells.iter().enumerate() {
+            let index = self.memory.start + i as isize;
+            let marker = if index == self.mp { ">" } else { " " };
+            writeln!(f)?;
+            write!(f, "  {} cell {}: {}", marker, index, cell)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for RuntimeErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeErrorKind::MemoryUnderflow => write!(f, "memory pointer underflow"),
+            RuntimeErrorKind::OutOfMemory(max) => {
+                write!(f, "out of memory (the limit is {} cells)", max)
+            }
+            RuntimeErrorKind::UnexpectedEof => write!(f, "unexpected end of input"),
+            RuntimeErrorKind::StepLimitExceeded(limit) => {
+                write!(f, "step limit of {} instructions exceeded", limit)
+            }
+            RuntimeErrorKind::CellOverflow => write!(f, "cell overflow"),
+            RuntimeErrorKind::CellUnderflow => write!(f, "cell underflow"),
+        }
+    }
+}
+
+impl RuntimeErrorKind {
+    /// Explanation shown next to the failing instruction.
+    fn label(&self) -> &'static str {
+        match self {
+            RuntimeErrorKind::MemoryUnderflow => "moved the memory pointer left of the first cell",
+            RuntimeErrorKind::OutOfMemory(_) => "moved the memory pointer past the memory limit",
+            RuntimeErrorKind::UnexpectedEof => "tried to read past the end of the input",
+            RuntimeErrorKind::StepLimitExceeded(_) => "the limit was reached here",
+            RuntimeErrorKind::CellOverflow => "incremented the cell past it's maximum value",
+            RuntimeErrorKind::CellUnderflow => "decremented the cell below it's minimum value",
+        }
+    }
+}
+
+impl Error for BrainfuckError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BrainfuckError::Io(e) => Some(e),
+            BrainfuckError::Parse(e) => Some(e),
+            BrainfuckError::Runtime(e) => Some(e.as_ref()),
+            BrainfuckError::Internal(_) => None,
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+impl Error for RuntimeError {}
+
+impl From<io::Error> for BrainfuckError {
+    fn from(e: io::Error) -> Self {
+        BrainfuckError::Io(e)
+    }
+}
+
+impl From<ParseError> for BrainfuckError {
+    fn from(e: ParseError) -> Self {
+        BrainfuckError::Parse(e)
+    }
+}
+
+impl From<RuntimeError> for BrainfuckError {
+    fn from(e: RuntimeError) -> Self {
+        BrainfuckError::Runtime(Box::new(e))
+    }
+}
@@ -0,0 +1,160 @@
+use std::{
+    fmt,
+    io::{self, Write},
+    str::FromStr,
+};
+
+/// What ``,`` does when there is no input left.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EofMode {
+    /// The program is aborted with a runtime error.
+    Error,
+    /// The cell keeps it's current value, what most implementations do.
+    #[default]
+    Unchanged,
+    /// The cell is set to 0.
+    Zero,
+    /// The cell is set to -1, which is it's maximum value for unsigned cells.
+    MinusOne,
+}
+
+impl FromStr for EofMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(EofMode::Error),
+            "unchanged" => Ok(EofMode::Unchanged),
+            "zero" => Ok(EofMode::Zero),
+            "minus-one" => Ok(EofMode::MinusOne),
+            _ => Err(format!(
+                "unknown EOF mode '{}', expected error, unchanged, zero or minus-one",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for EofMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EofMode::Error => write!(f, "error"),
+            EofMode::Unchanged => write!(f, "unchanged"),
+            EofMode::Zero => write!(f, "zero"),
+            EofMode::MinusOne => write!(f, "minus-one"),
+        }
+    }
+}
+
+/// When the output of ``.`` is flushed to the underlying writer.
+/// The output is always flushed when the program ends, even if it fails.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FlushPolicy {
+    /// After every byte, like the output of a terminal.
+    Byte,
+    /// After every newline and before ``,`` reads input, so prompts are shown before waiting for input.
+    #[default]
+    Line,
+    /// Only before ``,`` reads input.
+    Input,
+    /// Only when the buffer is full.
+    Full,
+}
+
+/// Buffers the output of ``.`` according to a [`FlushPolicy`].
+#[derive(Debug)]
+pub(crate) struct Output<O: Write> {
+    inner: O,
+    buffer: Vec<u8>,
+    policy: FlushPolicy,
+}
+
+impl<O: Write> Output<O> {
+    pub const CAPACITY: usize = 8 * 1024;
+
+    pub fn new(inner: O, policy: FlushPolicy) -> Self {
+        Output {
+            inner,
+            buffer: Vec::with_capacity(Self::CAPACITY),
+            policy,
+        }
+    }
+
+    /// Writes ``byte``, flushing if the policy requires it.
+    #[inline]
+    pub fn write(&mut self, byte: u8) -> io::Result<()> {
+        self.buffer.push(byte);
+
+        match self.policy {
+            FlushPolicy::Byte => self.flush(),
+            FlushPolicy::Line if byte == b'\n' => self.flush(),
+            _ if self.buffer.len() >= Self::CAPACITY => self.write_buffer(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Flushes the output before the program waits for input, unless it's fully buffered.
+    #[inline]
+    pub fn before_input(&mut self) -> io::Result<()> {
+        match self.policy {
+            FlushPolicy::Full => Ok(()),
+            _ => self.flush(),
+        }
+    }
+
+    /// Writes the buffered output and flushes the underlying writer.
+    /// The writer is flushed even if the buffer is empty, a full buffer is written to it without flushing.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            self.write_buffer()?;
+        }
+
+        self.inner.flush()
+    }
+
+    pub fn get_ref(&self) -> &O {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut O {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> O {
+        self.inner
+    }
+
+    fn write_buffer(&mut self) -> io::Result<()> {
+        let result = self.inner.write_all(&self.buffer);
+        self.buffer.clear();
+        result
+    }
+}
+
+impl FromStr for FlushPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "byte" => Ok(FlushPolicy::Byte),
+            "line" => Ok(FlushPolicy::Line),
+            "input" => Ok(FlushPolicy::Input),
+            "full" => Ok(FlushPolicy::Full),
+            _ => Err(format!(
+                "unknown flush policy '{}', expected byte, line, input or full",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for FlushPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FlushPolicy::Byte => write!(f, "byte"),
+            FlushPolicy::Line => write!(f, "line"),
+            FlushPolicy::Input => write!(f, "input"),
+            FlushPolicy::Full => write!(f, "full"),
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use std::fmt::{self, Write};
+
+use crate::source::{SourceMap, Span};
+
+/// An instruction of a parsed program.
+/// The parser emits one instruction per brainfuck command, the optimizer combines and replaces them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    /// Adds to the current cell, ``+`` and ``-`` or several of them folded together.
+    /// Negative values subtract.
+    Add(i32),
+    /// Moves the memory pointer, ``>`` and ``<`` or several of them folded together.
+    /// Negative values move to the left.
+    Move(isize),
+    /// Writes the current cell to the output, ``.``
+    Out,
+    /// Reads a byte of input into the current cell, ``,``
+    In,
+    /// ``[``, jumps past the instruction at the index if the current cell is 0.
+    /// The index is the matching [`Op::Jnz`], see [`crate::parser::link`].
+    Jz(usize),
+    /// ``]``, jumps back to the instruction after the index if the current cell isn't 0.
+    /// The index is the matching [`Op::Jz`].
+    Jnz(usize),
+    /// Sets the current cell to 0, replaces ``[-]`` and ``[+]``.
+    Clear,
+    /// Sets the current cell to a constant, replaces ``[-]`` followed by ``+`` or ``-``.
+    Set(i64),
+    /// Adds the current cell times ``factor`` to the cell ``offset`` cells away, if the current cell isn't 0.
+    /// Replaces loops like ``[->+>++<<]`` together with a [`Op::Clear`].
+    MulAdd { offset: isize, factor: i32 },
+    /// Moves the memory pointer by a stride until it points at a zero cell. Replaces loops like ``[>]`` or ``[<<]``.
+    Scan(isize),
+    /// [`Op::Add`] on the cell ``offset`` cells away from the memory pointer, without moving it.
+    /// Like the other ``*At`` instructions it's emitted by [`crate::optimizer::optimize_offsets`].
+    AddAt(isize, i32),
+    /// [`Op::Set`] on the cell ``offset`` cells away, also replaces [`Op::Clear`].
+    SetAt(isize, i64),
+    /// [`Op::Out`] of the cell ``offset`` cells away.
+    OutAt(isize),
+    /// [`Op::In`] into the cell ``offset`` cells away.
+    InAt(isize),
+}
+
+/// Renders ``ops`` as pseudo-assembly, one instruction per line with it's index, the location it came from
+/// and the brainfuck instructions it replaces (and how many if there's more than one):
+///
+/// ```text
+/// ; example.bf: 4 instructions
+/// 0  add 3     ; 1:1-3  +++ (3)
+/// 1  scan +1   ; 1:4-6  [>] (3)
+/// 2  out [-1]  ; 1:7-8  <. (2)
+/// 3  move -1   ; 1:7    <
+/// ```
+pub fn dump(ops: &[Op], source_map: &SourceMap) -> String {
+    let lines: Vec<(String, String, String)> = ops
+        .iter()
+        .enumerate()
+        .map(|(i, op)| {
+            let (location, excerpt) = match source_map.span(i) {
+                Some(span) => (location(span), excerpt(span, source_map)),
+                None => (String::new(), String::new()),
+            };
+            (op.to_string(), location, excerpt)
+        })
+        .collect();
+
+    let index_width = ops.len().saturating_sub(1).to_string().len();
+    let op_width = lines.iter().map(|(op, _, _)| op.len()).max().unwrap_or(0);
+    let location_width = lines.iter().map(|(_, l, _)| l.len()).max().unwrap_or(0);
+
+    let mut text = format!(
+        "; {}: {} instructions\n",
+        source_map.source().name(),
+        ops.len()
+    );
+    for (i, (op, location, excerpt)) in lines.iter().enumerate() {
+        // writing to a String can't fail
+        let _ = writeln!(
+            text,
+            "{:>index_width$}  {:<op_width$}  ; {:<location_width$}  {}",
+            i, op, location, excerpt
+        );
+    }
+
+    text
+}
+
+/// ``line:column`` of the start of ``span`` and where it ends, if that's somewhere else.
+fn location(span: Span) -> String {
+    let (start, end) = (span.start, span.end);
+    if start == end {
+        format!("{}:{}", start.line, start.column)
+    } else if start.line == end.line {
+        format!("{}:{}-{}", start.line, start.column, end.column)
+    } else {
+        format!(
+            "{}:{}-{}:{}",
+            start.line, start.column, end.line, end.column
+        )
+    }
+}
+
+/// The brainfuck instructions in ``span``, shortened if there are a lot of them.
+fn excerpt(span: Span, source_map: &SourceMap) -> String {
+    const MAX_LEN: usize = 24;
+
+    let instructions: Vec<u8> = source_map.instructions(span).collect();
+
+    let mut excerpt: String = instructions
+        .iter()
+        .take(MAX_LEN)
+        .map(|c| *c as char)
+        .collect();
+    if instructions.len() > MAX_LEN {
+        excerpt.push_str("...");
+    }
+    if instructions.len() > 1 {
+        let _ = write!(excerpt, " ({})", instructions.len());
+    }
+
+    excerpt
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Op::Add(n) => write!(f, "add {}", n),
+            Op::Move(n) => write!(f, "move {}", n),
+            Op::Out => write!(f, "out"),
+            Op::In => write!(f, "in"),
+            Op::Jz(target) => write!(f, "jz {}", target),
+            Op::Jnz(target) => write!(f, "jnz {}", target),
+            Op::Clear => write!(f, "clear"),
+            Op::Set(value) => write!(f, "set {}", value),
+            Op::MulAdd { offset, factor } => write!(f, "muladd [{:+}], {}", offset, factor),
+            Op::Scan(stride) => write!(f, "scan {:+}", stride),
+            Op::AddAt(offset, n) => write!(f, "add [{:+}], {}", offset, n),
+            Op::SetAt(offset, value) => write!(f, "set [{:+}], {}", offset, value),
+            Op::OutAt(offset) => write!(f, "out [{:+}]", offset),
+            Op::InAt(offset) => write!(f, "in [{:+}]", offset),
+        }
+    }
+}
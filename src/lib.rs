@@ -0,0 +1,61 @@
+//! A fast brainfuck interpreter written in rust.
+//!
+//! The [`VM`] loads a program according to it's [`VMOptions`] and runs it:
+//!
+//! ```no_run
+//! use brainfuck::{VMOptions, VM};
+//! use std::fs::File;
+//!
+//! let options = VMOptions {
+//!     disable_comments: true,
+//!     ..VMOptions::new(File::open("./brainfuck_programs/hello_world.bf")?)
+//! };
+//!
+//! let mut vm = VM::new(options)?;
+//! vm.run()?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! Programs can be read from anything that implements [`std::io::Read`],
+//! or be given directly as a string:
+//!
+//! ```no_run
+//! let mut vm = brainfuck::VM::from_source("++++++++[>++++++++<-]>+.")?;
+//! vm.run()?;
+//! # Ok::<(), brainfuck::BrainfuckError>(())
+//! ```
+//!
+//! By default the VM uses stdin and stdout, [`VM::with_io`] takes any reader and writer instead:
+//!
+//! ```
+//! use brainfuck::{VMOptions, VM};
+//!
+//! let options = VMOptions::new(",+.".as_bytes());
+//! let mut vm = VM::with_io(options, "a".as_bytes(), Vec::new())?;
+//! vm.run()?;
+//!
+//! assert_eq!(b"b", &vm.into_output()[..]);
+//! # Ok::<(), brainfuck::BrainfuckError>(())
+//! ```
+//!
+//! The [`parser`] and [`optimizer`] can also be used on their own, programs are a list of [`ir::Op`]s.
+
+mod cell;
+mod error;
+mod io;
+pub mod ir;
+pub mod optimizer;
+pub mod parser;
+pub mod source;
+mod tape;
+mod vm;
+
+pub use cell::{ArithmeticMode, CellSize};
+pub use error::{
+    BrainfuckError, Diagnostic, DiagnosticKind, LoopFrame, MemoryWindow, ParseError, RuntimeError,
+    RuntimeErrorKind,
+};
+pub use io::{EofMode, FlushPolicy};
+pub use optimizer::{Pass, Pipeline};
+pub use tape::TapeMode;
+pub use vm::{VMOptions, VM};
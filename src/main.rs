@@ -1,44 +1,253 @@
+use brainfuck::{
+    ArithmeticMode, BrainfuckError, CellSize, EofMode, FlushPolicy, Pass, Pipeline,
+    RuntimeErrorKind, TapeMode, VMOptions, VM,
+};
 use clap::Parser;
-use std::{fs::File, path::PathBuf};
-
-mod brainfuck;
-
-use brainfuck::{VMOptions, VM};
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
 
 #[derive(Debug, Parser)]
-#[clap(version, long_about = "A fast brainfuck interpreter written in rust.")]
+#[clap(
+    version,
+    long_about = "A fast brainfuck interpreter written in rust.",
+    after_help = "EXIT CODES:
+    0   Success
+    1   The program or input file could not be opened
+    3   The program could not be parsed
+    4   Reading input or writing output failed
+    5   The memory pointer left the memory or the memory limit was reached
+    6   The program tried to read past the end of input with --eof error
+    7   The step limit was exceeded
+    8   A cell overflowed or underflowed with --arithmetic trapping
+    70  Internal error"
+)]
 struct Opt {
-    /// Disables optimizer (might improve performance in small programs)
+    /// Disables optimizer (might improve performance in small programs), the same as -O0
     #[clap(long)]
     no_optimize: bool,
 
+    /// Optimization level: 0 runs no passes, 1 folds runs of instructions, 2 also replaces common loops,
+    /// 3 also removes dead loops and fuses pointer moves
+    #[clap(
+        short = 'O',
+        value_name = "LEVEL",
+        default_value_t = 3,
+        possible_values = ["0", "1", "2", "3"]
+    )]
+    opt_level: u8,
+
+    /// Runs this optimization pass even if the level doesn't include it (can be repeated)
+    #[clap(long, value_name = "PASS", possible_values = PASSES)]
+    enable_pass: Vec<Pass>,
+
+    /// Skips this optimization pass (can be repeated)
+    #[clap(long, value_name = "PASS", possible_values = PASSES)]
+    disable_pass: Vec<Pass>,
+
     /// Disables comment Characters (# and ;)
     #[clap(long)]
     no_comments: bool,
 
-    /// Input File
-    #[clap(parse(from_os_str))]
-    input: PathBuf,
+    /// Aborts the program after executing this many instructions
+    #[clap(long, value_name = "STEPS")]
+    step_limit: Option<u64>,
+
+    /// Number of cells the memory starts with
+    #[clap(long, value_name = "CELLS", default_value_t = 1024)]
+    memory_size: usize,
+
+    /// Number of cells the memory can grow to
+    #[clap(long, value_name = "CELLS", default_value_t = 1 << 26)]
+    max_memory_size: usize,
+
+    /// What happens when the memory pointer moves past the ends of the memory
+    #[clap(
+        long,
+        value_name = "MODE",
+        default_value_t = TapeMode::Error,
+        possible_values = ["error", "wrap", "infinite"]
+    )]
+    tape: TapeMode,
+
+    /// Width of a memory cell in bits
+    #[clap(
+        long,
+        value_name = "BITS",
+        default_value_t = CellSize::Bits8,
+        possible_values = ["8", "16", "32", "64"]
+    )]
+    cell_size: CellSize,
+
+    /// What happens when a cell is incremented past it's maximum or decremented below it's minimum value
+    #[clap(
+        long,
+        value_name = "MODE",
+        default_value_t = ArithmeticMode::Wrapping,
+        possible_values = ["wrapping", "saturating", "trapping"]
+    )]
+    arithmetic: ArithmeticMode,
+
+    /// Interprets cells as signed integers (only changes saturating and trapping arithmetic, input and memory dumps)
+    #[clap(long)]
+    signed: bool,
+
+    /// What ``,`` does when there is no input left
+    #[clap(
+        long,
+        value_name = "MODE",
+        default_value_t = EofMode::Unchanged,
+        possible_values = ["error", "unchanged", "zero", "minus-one"]
+    )]
+    eof: EofMode,
+
+    /// When the output is flushed
+    #[clap(
+        long,
+        value_name = "POLICY",
+        default_value_t = FlushPolicy::Line,
+        possible_values = ["byte", "line", "input", "full"]
+    )]
+    flush: FlushPolicy,
+
+    /// Reads the input of the program from this file instead of stdin (- for stdin)
+    #[clap(long, value_name = "FILE", parse(from_os_str))]
+    input: Option<PathBuf>,
+
+    /// Uses this text as the input of the program instead of stdin
+    #[clap(long, value_name = "TEXT", conflicts_with = "input")]
+    input_string: Option<String>,
+
+    /// Prints the program after optimizing instead of running it (ir: one instruction per line)
+    #[clap(long, value_name = "FORMAT", possible_values = ["ir"])]
+    emit: Option<String>,
+
+    /// Prints the optimized program as pseudo-assembly instead of running it, the same as --emit=ir
+    #[clap(long)]
+    dump_ir: bool,
+
+    /// Runs this program instead of reading it from a file
+    #[clap(short, long, value_name = "PROGRAM", conflicts_with = "program")]
+    eval: Option<String>,
+
+    /// Program File (- to read it from stdin)
+    #[clap(parse(from_os_str), required_unless_present = "eval")]
+    program: Option<PathBuf>,
 }
 
-fn main() {
+const PASSES: [&str; 6] = [
+    "fold",
+    "dead-code",
+    "clear-loops",
+    "multiply-loops",
+    "scan-loops",
+    "offset-fusion",
+];
+
+fn main() -> ExitCode {
     let opt = Opt::parse();
-    let display = opt.input.display();
 
-    let file = match File::open(&opt.input) {
-        Err(why) => {
-            eprintln!("couldn't open {}: {}", display, why);
-            return;
-        }
-        Ok(file) => file,
+    let (program, name): (Box<dyn Read>, String) = match (&opt.eval, &opt.program) {
+        (Some(source), _) => (
+            Box::new(io::Cursor::new(source.clone().into_bytes())),
+            String::from("<eval>"),
+        ),
+        (None, Some(path)) => match open(path) {
+            Err(why) => {
+                eprintln!("couldn't open {}: {}", path.display(), why);
+                return ExitCode::from(1);
+            }
+            Ok(file) => file,
+        },
+        // clap requires one of them
+        (None, None) => unreachable!(),
+    };
+
+    let input: Box<dyn Read> = match (&opt.input, &opt.input_string) {
+        (_, Some(text)) => Box::new(io::Cursor::new(text.clone().into_bytes())),
+        (Some(path), None) => match open(path) {
+            Err(why) => {
+                eprintln!("couldn't open {}: {}", path.display(), why);
+                return ExitCode::from(1);
+            }
+            Ok((file, _)) => file,
+        },
+        (None, None) => Box::new(io::stdin()),
     };
 
+    let mut pipeline = Pipeline::level(opt.opt_level);
+    for pass in &opt.enable_pass {
+        pipeline.enable(*pass);
+    }
+    for pass in &opt.disable_pass {
+        pipeline.disable(*pass);
+    }
+
     let options = VMOptions {
-        program: file,
+        program,
+        name: Some(name),
         disable_optimizer: opt.no_optimize,
+        pipeline,
         disable_comments: opt.no_comments,
+        step_limit: opt.step_limit,
+        memory_size: opt.memory_size,
+        max_memory_size: opt.max_memory_size,
+        tape_mode: opt.tape,
+        cell_size: opt.cell_size,
+        arithmetic: opt.arithmetic,
+        signed_cells: opt.signed,
+        eof: opt.eof,
+        flush: opt.flush,
     };
 
-    let mut vm = VM::new(options);
-    vm.run();
+    // ir is the only format so far
+    let dump_ir = opt.dump_ir || opt.emit.is_some();
+
+    let result = VM::with_io(options, input, io::stdout().lock()).and_then(|mut vm| {
+        if dump_ir {
+            print!("{}", vm.dump_ir());
+            Ok(())
+        } else {
+            vm.run()
+        }
+    });
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            match e {
+                // parse and runtime errors are already formatted as full reports
+                BrainfuckError::Parse(_) | BrainfuckError::Runtime(_) => eprintln!("{}", e),
+                _ => eprintln!("error: {}", e),
+            }
+            ExitCode::from(exit_code(&e))
+        }
+    }
+}
+
+/// Opens ``path`` for reading, ``-`` is stdin. Also returns the name to show in error messages.
+fn open(path: &Path) -> io::Result<(Box<dyn Read>, String)> {
+    if path == Path::new("-") {
+        Ok((Box::new(io::stdin()), String::from("<stdin>")))
+    } else {
+        Ok((Box::new(File::open(path)?), path.display().to_string()))
+    }
+}
+
+/// Maps each kind of error to the exit code listed in the help text.
+fn exit_code(error: &BrainfuckError) -> u8 {
+    match error {
+        BrainfuckError::Parse(_) => 3,
+        BrainfuckError::Io(_) => 4,
+        BrainfuckError::Runtime(e) => match e.kind {
+            RuntimeErrorKind::MemoryUnderflow | RuntimeErrorKind::OutOfMemory(_) => 5,
+            RuntimeErrorKind::UnexpectedEof => 6,
+            RuntimeErrorKind::StepLimitExceeded(_) => 7,
+            RuntimeErrorKind::CellOverflow | RuntimeErrorKind::CellUnderflow => 8,
+        },
+        BrainfuckError::Internal(_) => 70,
+    }
 }
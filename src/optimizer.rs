@@ -0,0 +1,456 @@
+use std::{fmt, str::FromStr};
+
+use crate::{ir::Op, parser::Program, source::Span, ArithmeticMode};
+
+/// An optimization pass, [`Pass::ALL`] lists them in the order the [`Pipeline`] runs them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pass {
+    /// Combines runs of ``+``, ``-``, ``>`` and ``<``, see [`optimize_successive`].
+    Fold,
+    /// See [`remove_dead_loops`].
+    DeadCode,
+    /// See [`optimize_clear_loops`], only runs with wrapping arithmetic.
+    ClearLoops,
+    /// See [`optimize_multiply_loops`], only runs with wrapping arithmetic.
+    MultiplyLoops,
+    /// See [`optimize_scan_loops`].
+    ScanLoops,
+    /// See [`optimize_offsets`].
+    OffsetFusion,
+}
+
+impl Pass {
+    pub const ALL: [Pass; 6] = [
+        Pass::Fold,
+        Pass::DeadCode,
+        Pass::ClearLoops,
+        Pass::MultiplyLoops,
+        Pass::ScanLoops,
+        Pass::OffsetFusion,
+    ];
+
+    /// The lowest optimization level that runs the pass.
+    pub fn level(self) -> u8 {
+        match self {
+            Pass::Fold => 1,
+            Pass::ClearLoops | Pass::MultiplyLoops | Pass::ScanLoops => 2,
+            Pass::DeadCode | Pass::OffsetFusion => 3,
+        }
+    }
+
+    /// Wether the pass changes what the program does with saturating or trapping arithmetic.
+    pub fn requires_wrapping(self) -> bool {
+        matches!(self, Pass::ClearLoops | Pass::MultiplyLoops)
+    }
+
+    /// Runs the pass on ``program``.
+    pub fn run(self, program: Program) -> Program {
+        match self {
+            Pass::Fold => optimize_successive(program),
+            Pass::ClearLoops => optimize_clear_loops(program),
+            Pass::MultiplyLoops => optimize_multiply_loops(program),
+            Pass::ScanLoops => optimize_scan_loops(program),
+            Pass::DeadCode => remove_dead_loops(program),
+            Pass::OffsetFusion => optimize_offsets(program),
+        }
+    }
+}
+
+/// Which passes run when a program is loaded, the default is every pass (``-O3``).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pipeline {
+    enabled: [bool; Pass::ALL.len()],
+}
+
+impl Pipeline {
+    /// The passes of an optimization level: 0 runs none, 1 only folds instructions, 2 also replaces loops
+    /// and 3 runs every pass. Levels above 3 are the same as 3.
+    pub fn level(level: u8) -> Self {
+        Pipeline {
+            enabled: Pass::ALL.map(|pass| pass.level() <= level),
+        }
+    }
+
+    pub fn enable(&mut self, pass: Pass) {
+        self.enabled[pass as usize] = true;
+    }
+
+    pub fn disable(&mut self, pass: Pass) {
+        self.enabled[pass as usize] = false;
+    }
+
+    pub fn is_enabled(&self, pass: Pass) -> bool {
+        self.enabled[pass as usize]
+    }
+
+    /// Runs the enabled passes in order, skipping the ones that require wrapping arithmetic unless ``arithmetic``
+    /// is [`ArithmeticMode::Wrapping`]. The jump targets have to be linked afterwards with [`crate::parser::link`].
+    pub fn run(&self, mut program: Program, arithmetic: ArithmeticMode) -> Program {
+        for pass in Pass::ALL {
+            if self.is_enabled(pass)
+                && (arithmetic == ArithmeticMode::Wrapping || !pass.requires_wrapping())
+            {
+                program = pass.run(program);
+            }
+        }
+
+        program
+    }
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Pipeline::level(3)
+    }
+}
+
+/// Optimizes successive '+' '-' '>' and '<' calls by combining them.
+/// For Example, '++++' would turn into Add(4).
+/// Only instructions that go in the same direction are combined, so ``+-`` still overflows with trapping arithmetic.
+/// The span of a combined instruction covers all the instructions it replaces.
+/// Like every pass, it leaves the jump targets outdated, [`crate::parser::link`] has to run afterwards.
+pub fn optimize_successive(program: Program) -> Program {
+    rewrite(program, |ops, spans, i, optimized| {
+        let mut op = ops[i];
+        let mut last = i;
+        while let Some(folded) = ops.get(last + 1).and_then(|next| fold(op, *next)) {
+            op = folded;
+            last += 1;
+        }
+
+        if last == i {
+            return None;
+        }
+
+        optimized.push(op, span_of(spans, i, last));
+        Some(last + 1)
+    })
+}
+
+/// Combines two adds or moves in the same direction, ``None`` if they can't be combined.
+fn fold(op: Op, next: Op) -> Option<Op> {
+    match (op, next) {
+        (Op::Add(a), Op::Add(b)) if a.signum() == b.signum() => a.checked_add(b).map(Op::Add),
+        (Op::Move(a), Op::Move(b)) if a.signum() == b.signum() => a.checked_add(b).map(Op::Move),
+        _ => None,
+    }
+}
+
+/// Replaces loops that clear the current cell with [`Op::Clear`]: ``[-]``, ``[+]`` and loops
+/// that add or subtract any odd number, like ``[---]`` (which ``optimize_successive`` already folded).
+/// If the loop is followed by ``+`` or ``-`` they are combined into a [`Op::Set`].
+/// Only valid with wrapping arithmetic, with saturating or trapping arithmetic these loops don't always clear the cell.
+pub fn optimize_clear_loops(program: Program) -> Program {
+    rewrite(program, |ops, spans, i, optimized| {
+        let end = clear_loop(ops, i)?;
+
+        let mut value = 0;
+        let mut last = end;
+        while let Some(Op::Add(n)) = ops.get(last + 1) {
+            value += *n as i64;
+            last += 1;
+        }
+
+        let op = if value == 0 {
+            Op::Clear
+        } else {
+            Op::Set(value)
+        };
+        optimized.push(op, span_of(spans, i, last));
+        Some(last + 1)
+    })
+}
+
+/// Returns the index of the ``]`` if the loop starting at ``start`` clears the current cell.
+fn clear_loop(ops: &[Op], start: usize) -> Option<usize> {
+    match ops[start..] {
+        // adding an odd number reaches 0 for every cell size
+        [Op::Jz(_), Op::Add(n), Op::Jnz(_), ..] if n % 2 != 0 => Some(start + 2),
+        _ => None,
+    }
+}
+
+/// Replaces loops that move the current cell to other cells, like ``[->+>++<<]``, with a [`Op::MulAdd`]
+/// for every cell they change and a [`Op::Clear`].
+/// These loops may only contain ``+``, ``-``, ``>`` and ``<``, have to end at the cell they started at,
+/// have to decrement it by exactly one per iteration and may not move past the cells they change.
+/// Only valid with wrapping arithmetic, like ``optimize_clear_loops``.
+pub fn optimize_multiply_loops(program: Program) -> Program {
+    rewrite(program, |ops, spans, i, optimized| {
+        let (targets, end) = multiply_loop(ops, i)?;
+
+        let span = span_of(spans, i, end);
+        for (offset, factor) in targets {
+            optimized.push(Op::MulAdd { offset, factor }, span);
+        }
+        optimized.push(Op::Clear, span);
+        Some(end + 1)
+    })
+}
+
+/// Replaces loops that search for a zero cell, like ``[>]`` or ``[<<]``, with a [`Op::Scan`].
+/// Doesn't change any cells, so it's valid for every kind of arithmetic.
+pub fn optimize_scan_loops(program: Program) -> Program {
+    rewrite(program, |ops, spans, i, optimized| match ops[i..] {
+        [Op::Jz(_), Op::Move(stride), Op::Jnz(_), ..] => {
+            optimized.push(Op::Scan(stride), span_of(spans, i, i + 2));
+            Some(i + 3)
+        }
+        _ => None,
+    })
+}
+
+/// Removes loops that are never entered because the current cell is always 0 at their ``[``:
+/// loops at the start of the program (often used for comments) and right after another loop.
+/// Runs before loops like ``[-]`` are replaced, so they still end with a ``]`` and ``[-][<+>-]`` is caught too.
+pub fn remove_dead_loops(program: Program) -> Program {
+    // every cell is 0 when the program starts
+    let mut zero = true;
+    rewrite(program, |ops, _, i, _| {
+        if !(zero && matches!(ops[i], Op::Jz(_))) {
+            zero = matches!(ops[i], Op::Jnz(_));
+            return None;
+        }
+
+        // the jump targets aren't linked between passes, so the matching ] has to be searched
+        let mut depth = 0;
+        for (j, op) in ops.iter().enumerate().skip(i) {
+            match op {
+                Op::Jz(_) => depth += 1,
+                Op::Jnz(_) if depth == 1 => return Some(j + 1),
+                Op::Jnz(_) => depth -= 1,
+                _ => continue,
+            }
+        }
+
+        None
+    })
+}
+
+/// Fuses the pointer moves of every basic block into the instructions that use the cells, so ``>+>++<<-``
+/// becomes ``AddAt(1, 1) AddAt(2, 2) Add(-1)``. The net move of the block is applied once at it's end,
+/// blocks end at loops, scans and multiplications (which need the memory pointer at their cell).
+/// Moving only fails past the ends of the memory, so if the pointer turns around at a cell that isn't used
+/// the moves up to there are applied right away, which makes the same programs fail.
+pub fn optimize_offsets(program: Program) -> Program {
+    let Program { ops, source_map } = program;
+    let spans = source_map.spans();
+
+    let mut optimized = Optimized {
+        ops: Vec::with_capacity(ops.len()),
+        spans: Vec::with_capacity(ops.len()),
+    };
+    let mut pending = PendingMove::default();
+    // the first move since the last instruction, the span of the next one includes the moves to it's cell
+    // because they are what fails if the cell doesn't exist
+    let mut moved_at: Option<usize> = None;
+    for (i, op) in ops.iter().enumerate() {
+        match *op {
+            Op::Move(n) => {
+                pending.move_by(n, i, &mut optimized, spans);
+                moved_at.get_or_insert(i);
+            }
+            Op::Add(_) | Op::Clear | Op::Set(_) | Op::Out | Op::In => {
+                pending.touch();
+                let first = moved_at.take().unwrap_or(i);
+                optimized.push(at(pending.offset, *op), span_of(spans, first, i));
+            }
+            _ => {
+                pending.flush(&mut optimized, spans);
+                moved_at = None;
+                optimized.push(*op, spans[i]);
+            }
+        }
+    }
+    pending.flush(&mut optimized, spans);
+
+    Program {
+        ops: optimized.ops,
+        source_map: source_map.with_spans(optimized.spans),
+    }
+}
+
+/// Returns the variant of ``op`` that uses the cell ``offset`` cells away from the memory pointer.
+fn at(offset: isize, op: Op) -> Op {
+    match op {
+        _ if offset == 0 => op,
+        Op::Add(n) => Op::AddAt(offset, n),
+        Op::Clear => Op::SetAt(offset, 0),
+        Op::Set(value) => Op::SetAt(offset, value),
+        Op::Out => Op::OutAt(offset),
+        Op::In => Op::InAt(offset),
+        _ => op,
+    }
+}
+
+/// The moves of the current basic block that [`optimize_offsets`] hasn't applied yet.
+#[derive(Default)]
+struct PendingMove {
+    /// Where the memory pointer would be
+    offset: isize,
+    /// The cells from ``low`` to ``high`` are used by an instruction, so moving there can't fail.
+    low: isize,
+    high: isize,
+    /// Index of the first and the last move
+    moves: Option<(usize, usize)>,
+}
+
+impl PendingMove {
+    fn move_by(&mut self, n: isize, i: usize, optimized: &mut Optimized, spans: &[Span]) {
+        let turns = (self.offset < self.low && n > 0) || (self.offset > self.high && n < 0);
+        if turns {
+            self.flush(optimized, spans);
+        }
+
+        self.offset += n;
+        self.moves = Some((self.moves.map_or(i, |(first, _)| first), i));
+    }
+
+    /// Marks the cell at ``offset`` as used.
+    fn touch(&mut self) {
+        self.low = self.low.min(self.offset);
+        self.high = self.high.max(self.offset);
+    }
+
+    /// Applies the pending moves.
+    fn flush(&mut self, optimized: &mut Optimized, spans: &[Span]) {
+        if let Some((first, last)) = self.moves {
+            if self.offset != 0 {
+                optimized.push(Op::Move(self.offset), span_of(spans, first, last));
+            }
+        }
+
+        *self = PendingMove::default();
+    }
+}
+
+/// Runs ``replace`` at every instruction, it either pushes a replacement and returns the index of the
+/// next instruction or returns ``None`` to keep the instruction as it is.
+fn rewrite<F>(program: Program, mut replace: F) -> Program
+where
+    F: FnMut(&[Op], &[Span], usize, &mut Optimized) -> Option<usize>,
+{
+    let Program { ops, source_map } = program;
+    let spans = source_map.spans();
+
+    let mut optimized = Optimized {
+        ops: Vec::with_capacity(ops.len()),
+        spans: Vec::with_capacity(ops.len()),
+    };
+    let mut i = 0;
+    while i < ops.len() {
+        match replace(&ops, spans, i, &mut optimized) {
+            Some(next) => i = next,
+            None => {
+                optimized.push(ops[i], spans[i]);
+                i += 1;
+            }
+        }
+    }
+
+    Program {
+        ops: optimized.ops,
+        source_map: source_map.with_spans(optimized.spans),
+    }
+}
+
+/// Returns the offsets and factors and the index of the ``]`` if the loop starting at ``start``
+/// is a multiply loop.
+fn multiply_loop(ops: &[Op], start: usize) -> Option<(Vec<(isize, i32)>, usize)> {
+    if !matches!(ops[start], Op::Jz(_)) {
+        return None;
+    }
+
+    // the change of every cell the loop touches, in the order they are first touched
+    let mut changes: Vec<(isize, i64)> = Vec::new();
+    let mut offset: isize = 0;
+    // the lowest and highest cell the loop moves to
+    let (mut low, mut high) = (0, 0);
+    let mut end = start + 1;
+    loop {
+        match *ops.get(end)? {
+            Op::Jnz(_) => break,
+            Op::Add(n) => match changes.iter_mut().find(|(o, _)| *o == offset) {
+                Some((_, change)) => *change += n as i64,
+                None => changes.push((offset, n as i64)),
+            },
+            Op::Move(n) => {
+                offset = offset.checked_add(n)?;
+                low = low.min(offset);
+                high = high.max(offset);
+            }
+            _ => return None,
+        }
+        end += 1;
+    }
+
+    if offset != 0 || !changes.contains(&(0, -1)) {
+        return None;
+    }
+
+    let targets = changes
+        .into_iter()
+        .filter(|(offset, change)| *offset != 0 && *change != 0)
+        .map(|(offset, change)| Some((offset, i32::try_from(change).ok()?)))
+        .collect::<Option<Vec<_>>>()?;
+
+    // the MulAdds only move as far as the cells they change, a loop that moves further (like ``[<>-]``)
+    // could fail where they don't
+    let offsets = targets.iter().map(|(offset, _)| *offset);
+    let covered = offsets.clone().min().unwrap_or(0).min(0)..=offsets.max().unwrap_or(0).max(0);
+    if !covered.contains(&low) || !covered.contains(&high) {
+        return None;
+    }
+
+    Some((targets, end))
+}
+
+/// The optimized program while it's being built.
+struct Optimized {
+    ops: Vec<Op>,
+    spans: Vec<Span>,
+}
+
+impl Optimized {
+    fn push(&mut self, op: Op, span: Span) {
+        self.ops.push(op);
+        self.spans.push(span);
+    }
+}
+
+/// Returns the span from the start of ``spans[first]`` to the end of ``spans[last]``.
+fn span_of(spans: &[Span], first: usize, last: usize) -> Span {
+    Span {
+        start: spans[first].start,
+        end: spans[last].end,
+    }
+}
+
+impl FromStr for Pass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Pass::ALL
+            .into_iter()
+            .find(|pass| pass.to_string() == s)
+            .ok_or_else(|| {
+                format!(
+                    "unknown pass '{}', expected fold, dead-code, clear-loops, multiply-loops, scan-loops or offset-fusion",
+                    s
+                )
+            })
+    }
+}
+
+impl fmt::Display for Pass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pass::Fold => write!(f, "fold"),
+            Pass::ClearLoops => write!(f, "clear-loops"),
+            Pass::MultiplyLoops => write!(f, "multiply-loops"),
+            Pass::ScanLoops => write!(f, "scan-loops"),
+            Pass::DeadCode => write!(f, "dead-code"),
+            Pass::OffsetFusion => write!(f, "offset-fusion"),
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use std::io::Read;
+
+use crate::{
+    ir::Op,
+    source::{Location, Source, SourceMap, Span},
+    BrainfuckError, Diagnostic, DiagnosticKind, ParseError,
+};
+
+/// A parsed program: it's instructions and where each of them is in the source code.
+#[derive(Debug, Clone, Default)]
+pub struct Program {
+    pub ops: Vec<Op>,
+    /// Maps ``ops[i]`` to it's source code
+    pub source_map: SourceMap,
+}
+
+/// Reads a brainfuck program and strips everything that isn't an instruction.
+/// If ``ignore_comments`` is set, everything after ``;`` or ``#`` up to the end of the line is ignored.
+/// ``name`` is used to refer to the program in error messages.
+pub fn parse<R: Read>(
+    mut program: R,
+    name: &str,
+    ignore_comments: bool,
+) -> Result<Program, BrainfuckError> {
+    let mut text = Vec::new();
+    program.read_to_end(&mut text)?;
+    let source = Source::new(name, text);
+
+    let mut ops: Vec<Op> = Vec::new();
+    let mut locations: Vec<Location> = Vec::new();
+    let mut ignore_to_line_end = false;
+    let mut line = 1;
+    let mut column = 0;
+
+    for (offset, c) in source.text().iter().enumerate() {
+        // UTF-8 continuation bytes don't start a new character
+        if c & 0b11000000 != 0b10000000 {
+            column += 1;
+        }
+
+        match c {
+            b'\n' | b'\r' => {
+                ignore_to_line_end = false;
+                if *c == b'\n' {
+                    line += 1;
+                    column = 0;
+                }
+            }
+
+            b'<' | b'>' | b'+' | b'-' | b'[' | b']' | b'.' | b',' => {
+                if !ignore_to_line_end {
+                    ops.push(match c {
+                        b'+' => Op::Add(1),
+                        b'-' => Op::Add(-1),
+                        b'>' => Op::Move(1),
+                        b'<' => Op::Move(-1),
+                        b'.' => Op::Out,
+                        b',' => Op::In,
+                        b'[' => Op::Jz(0),
+                        _ => Op::Jnz(0),
+                    });
+                    locations.push(Location {
+                        offset,
+                        line,
+                        column,
+                    });
+                }
+            }
+
+            b';' | b'#' => {
+                // ignore until EOL after comment char
+                if ignore_comments {
+                    ignore_to_line_end = true;
+                }
+            }
+
+            _ => continue,
+        }
+    }
+
+    validate_brackets(&ops, &locations, &source)?;
+    link(&mut ops).ok_or(BrainfuckError::Internal(
+        "Brackets mismatched after parsing",
+    ))?;
+
+    let spans = locations.into_iter().map(Span::at).collect();
+    Ok(Program {
+        ops,
+        source_map: SourceMap::new(source, spans),
+    })
+}
+
+/// Reports every ``]`` without a matching ``[`` and every ``[`` that is never closed.
+fn validate_brackets(
+    ops: &[Op],
+    locations: &[Location],
+    source: &Source,
+) -> Result<(), ParseError> {
+    let mut open: Vec<usize> = Vec::new();
+    let mut unmatched: Vec<(usize, DiagnosticKind)> = Vec::new();
+
+    for (i, op) in ops.iter().enumerate() {
+        match op {
+            Op::Jz(_) => open.push(i),
+            Op::Jnz(_) => {
+                if open.pop().is_none() {
+                    unmatched.push((i, DiagnosticKind::UnmatchedClosingBracket));
+                }
+            }
+            _ => continue,
+        }
+    }
+
+    let innermost = open.last().copied();
+    unmatched.extend(
+        open.into_iter()
+            .map(|i| (i, DiagnosticKind::UnclosedBracket)),
+    );
+
+    if unmatched.is_empty() {
+        return Ok(());
+    }
+
+    unmatched.sort_by_key(|(i, _)| *i);
+    let diagnostics = unmatched
+        .into_iter()
+        .map(|(i, kind)| Diagnostic {
+            innermost: match (kind, innermost) {
+                (DiagnosticKind::UnclosedBracket, Some(j)) if j != i => {
+                    Some(source.snippet(locations[j]))
+                }
+                _ => None,
+            },
+            kind,
+            snippet: source.snippet(locations[i]),
+        })
+        .collect();
+
+    Err(ParseError { diagnostics })
+}
+
+/// Points every [`Op::Jz`] and [`Op::Jnz`] at it's matching bracket, in a single pass with a stack of open brackets.
+/// Has to run again after instructions were added or removed, returns ``None`` if the brackets are mismatched.
+pub fn link(ops: &mut [Op]) -> Option<()> {
+    let mut open: Vec<usize> = Vec::new();
+    for i in 0..ops.len() {
+        match ops[i] {
+            Op::Jz(_) => open.push(i),
+            Op::Jnz(_) => {
+                let start = open.pop()?;
+                ops[start] = Op::Jz(i);
+                ops[i] = Op::Jnz(start);
+            }
+            _ => continue,
+        }
+    }
+
+    open.is_empty().then_some(())
+}
@@ -0,0 +1,216 @@
+use std::{fmt, ops::Range};
+
+/// The source code of a brainfuck program, used to point at instructions in error messages.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Source {
+    name: String,
+    text: Vec<u8>,
+    line_starts: Vec<usize>, // offset of the first byte of every line
+}
+
+/// Position of a single character in the source code.
+/// ``line`` and ``column`` start at 1, ``column`` counts characters, not bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// The part of the source code an instruction was created from, from ``start`` to ``end`` (inclusive).
+/// For unoptimized instructions both point at the same character.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: Location,
+    pub end: Location,
+}
+
+/// Maps every instruction of a (possibly optimized) program back to the source code it came from.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    source: Source,
+    spans: Vec<Span>,
+    instructions: Vec<usize>, // offset of every character the parser kept as an instruction
+}
+
+/// An excerpt of the source code pointing at one or more characters, rendered like rustc does:
+///
+/// ```text
+///  --> program.bf:1:3
+///   |
+/// 1 | ++[>+
+///   |   ^
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snippet {
+    pub name: String,
+    pub line: usize,
+    pub column: usize,
+    /// Number of characters the snippet points at
+    pub width: usize,
+    /// The complete line the character is on
+    pub text: String,
+}
+
+impl Source {
+    pub fn new(name: impl Into<String>, text: Vec<u8>) -> Self {
+        let mut line_starts = vec![0];
+        line_starts.extend(
+            text.iter()
+                .enumerate()
+                .filter(|(_, c)| **c == b'\n')
+                .map(|(i, _)| i + 1),
+        );
+
+        Source {
+            name: name.into(),
+            text,
+            line_starts,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn text(&self) -> &[u8] {
+        &self.text
+    }
+
+    /// Returns the text of ``line`` without it's line ending.
+    pub fn line(&self, line: usize) -> String {
+        let start = self.line_starts[line - 1];
+        let end = self
+            .line_starts
+            .get(line)
+            .map_or(self.text.len(), |next| next - 1);
+        let text = String::from_utf8_lossy(&self.text[start..end]);
+
+        text.trim_end_matches('\r').to_string()
+    }
+
+    /// Returns an excerpt of the source pointing at ``location``.
+    pub fn snippet(&self, location: Location) -> Snippet {
+        Snippet {
+            name: self.name.clone(),
+            line: location.line,
+            column: location.column,
+            width: 1,
+            text: self.line(location.line),
+        }
+    }
+
+    /// Returns an excerpt of the source pointing at ``span``.
+    /// Spans over multiple lines are cut off at the end of the first line.
+    pub fn span_snippet(&self, span: Span) -> Snippet {
+        let mut snippet = self.snippet(span.start);
+        snippet.width = if span.end.line == span.start.line {
+            span.end.column - span.start.column + 1
+        } else {
+            snippet.text.chars().count() + 1 - span.start.column
+        };
+
+        snippet
+    }
+}
+
+impl Span {
+    /// Creates a span covering a single character.
+    pub fn at(location: Location) -> Self {
+        Span {
+            start: location,
+            end: location,
+        }
+    }
+
+    /// Byte offsets of the span in the source code.
+    pub fn range(&self) -> Range<usize> {
+        self.start.offset..self.end.offset + 1
+    }
+}
+
+impl SourceMap {
+    /// ``spans[i]`` has to be the span of the i-th instruction of the parsed, unoptimized program.
+    pub fn new(source: Source, spans: Vec<Span>) -> Self {
+        let instructions = spans.iter().map(|span| span.start.offset).collect();
+        SourceMap {
+            source,
+            spans,
+            instructions,
+        }
+    }
+
+    pub fn source(&self) -> &Source {
+        &self.source
+    }
+
+    pub fn spans(&self) -> &[Span] {
+        &self.spans
+    }
+
+    /// Returns the span of the instruction at ``pp``.
+    pub fn span(&self, pp: usize) -> Option<Span> {
+        self.spans.get(pp).copied()
+    }
+
+    /// Returns the instructions in ``span``, without the comments between them.
+    pub fn instructions(&self, span: Span) -> impl Iterator<Item = u8> + '_ {
+        let range = span.range();
+        let start = self
+            .instructions
+            .partition_point(|offset| *offset < range.start);
+
+        self.instructions[start..]
+            .iter()
+            .take_while(move |offset| range.contains(offset))
+            .map(|offset| self.source.text[*offset])
+    }
+
+    /// Returns an excerpt of the source pointing at the instruction at ``pp``.
+    pub fn snippet(&self, pp: usize) -> Option<Snippet> {
+        self.span(pp).map(|span| self.source.span_snippet(span))
+    }
+
+    /// Replaces the spans, e.g. after optimizing the program.
+    pub fn with_spans(self, spans: Vec<Span>) -> Self {
+        SourceMap {
+            source: self.source,
+            spans,
+            instructions: self.instructions,
+        }
+    }
+}
+
+impl Snippet {
+    /// Renders the snippet with ``label`` next to the caret.
+    pub fn render(&self, f: &mut fmt::Formatter<'_>, label: &str) -> fmt::Result {
+        let gutter = " ".repeat(self.line.to_string().len());
+        // keep tabs so the caret lines up with the text above it
+        let padding: String = self
+            .text
+            .chars()
+            .take(self.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+
+        writeln!(
+            f,
+            "{}--> {}:{}:{}",
+            gutter, self.name, self.line, self.column
+        )?;
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", self.line, self.text)?;
+        write!(f, "{} | {}{}", gutter, padding, "^".repeat(self.width))?;
+        if !label.is_empty() {
+            write!(f, " {}", label)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Snippet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.render(f, "")
+    }
+}
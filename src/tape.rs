@@ -0,0 +1,345 @@
+use std::{fmt, str::FromStr};
+
+use crate::{
+    cell::{Cell, CellSize},
+    MemoryWindow, RuntimeErrorKind,
+};
+
+/// What happens when the memory pointer moves past either end of the tape.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TapeMode {
+    /// Moving left of the first cell is an error, the tape grows to the right.
+    #[default]
+    Error,
+    /// The tape has a fixed size of ``memory_size`` cells and the memory pointer wraps around at both ends.
+    Wrap,
+    /// The tape grows in both directions, cells left of the starting cell have negative indices.
+    Infinite,
+}
+
+/// The memory of the VM.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Tape<C: Cell> {
+    pub cells: Vec<C>,
+    pub mp: usize,   // MemoryPointer, index into cells
+    origin: usize,   // index of the starting cell in cells, > 0 if the tape grew to the left
+    start: usize,    // index of the leftmost cell the memory pointer reached
+    reached: usize,  // number of cells it reached, the rest is room to grow into
+    size: usize,     // initial size, used by reset()
+    max_size: usize, // maximum number of reached cells
+    mode: TapeMode,
+}
+
+/// The memory of the VM for each of the supported cell sizes.
+#[derive(Debug, Clone)]
+pub(crate) enum Memory {
+    U8(Tape<u8>),
+    U16(Tape<u16>),
+    U32(Tape<u32>),
+    U64(Tape<u64>),
+}
+
+/// Evaluates ``$e`` with ``$tape`` bound to the tape of ``$memory``, whatever it's cell size is.
+macro_rules! with_tape {
+    ($memory:expr, $tape:ident => $e:expr) => {
+        match $memory {
+            Memory::U8($tape) => $e,
+            Memory::U16($tape) => $e,
+            Memory::U32($tape) => $e,
+            Memory::U64($tape) => $e,
+        }
+    };
+}
+pub(crate) use with_tape;
+
+impl Memory {
+    pub fn new(cell_size: CellSize, size: usize, max_size: usize, mode: TapeMode) -> Self {
+        match cell_size {
+            CellSize::Bits8 => Memory::U8(Tape::new(size, max_size, mode)),
+            CellSize::Bits16 => Memory::U16(Tape::new(size, max_size, mode)),
+            CellSize::Bits32 => Memory::U32(Tape::new(size, max_size, mode)),
+            CellSize::Bits64 => Memory::U64(Tape::new(size, max_size, mode)),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        with_tape!(self, tape => tape.reset())
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Memory::U8(Tape::default())
+    }
+}
+
+impl<C: Cell> Tape<C> {
+    pub fn new(size: usize, max_size: usize, mode: TapeMode) -> Self {
+        let size = size.max(1);
+        Tape {
+            cells: vec![C::default(); size],
+            mp: 0,
+            origin: 0,
+            start: 0,
+            reached: size,
+            size,
+            max_size: max_size.max(size),
+            mode,
+        }
+    }
+
+    /// Moves the memory pointer ``n`` cells to the right.
+    #[inline]
+    pub fn move_right(&mut self, n: usize) -> Result<(), RuntimeErrorKind> {
+        let mp = self.mp + n;
+        if mp < self.end() {
+            self.mp = mp;
+            return Ok(());
+        }
+
+        match self.mode {
+            TapeMode::Wrap => self.mp = mp % self.cells.len(),
+            TapeMode::Error | TapeMode::Infinite => self.mp = self.grow_right(mp)?,
+        }
+
+        Ok(())
+    }
+
+    /// Moves the memory pointer ``n`` cells to the left.
+    #[inline]
+    pub fn move_left(&mut self, n: usize) -> Result<(), RuntimeErrorKind> {
+        if let Some(mp) = self.mp.checked_sub(n).filter(|mp| *mp >= self.start) {
+            self.mp = mp;
+            return Ok(());
+        }
+
+        match self.mode {
+            TapeMode::Error => return Err(RuntimeErrorKind::MemoryUnderflow),
+            TapeMode::Wrap => {
+                let len = self.cells.len();
+                self.mp = (self.mp + len - n % len) % len;
+            }
+            TapeMode::Infinite => self.mp = self.grow_left(self.mp as isize - n as isize)?,
+        }
+
+        Ok(())
+    }
+
+    /// Moves the memory pointer ``offset`` cells to the right, or to the left if it's negative.
+    #[inline]
+    pub fn move_by(&mut self, offset: isize) -> Result<(), RuntimeErrorKind> {
+        // moves within the tape are by far the most common, check for them first
+        let mp = self.mp.wrapping_add_signed(offset);
+        if self.is_reached(mp) {
+            self.mp = mp;
+            return Ok(());
+        }
+
+        self.move_past_end(offset)
+    }
+
+    /// Moves the memory pointer past either end of the tape, see [`TapeMode`].
+    #[cold]
+    #[inline(never)]
+    fn move_past_end(&mut self, offset: isize) -> Result<(), RuntimeErrorKind> {
+        if offset < 0 {
+            self.move_left(offset.unsigned_abs())
+        } else {
+            self.move_right(offset as usize)
+        }
+    }
+
+    /// Returns the index into ``cells`` of the cell ``offset`` cells away from the memory pointer.
+    /// Grows the tape or fails like moving there would, but leaves the memory pointer where it is.
+    #[inline]
+    pub fn index(&mut self, offset: isize) -> Result<usize, RuntimeErrorKind> {
+        let i = self.mp.wrapping_add_signed(offset);
+        if self.is_reached(i) {
+            return Ok(i);
+        }
+
+        // moving back can't fail, the tape already grew or wrapped around
+        self.move_by(offset)?;
+        let i = self.mp;
+        self.move_by(-offset)?;
+        Ok(i)
+    }
+
+    /// Moves the memory pointer ``stride`` cells at a time until it points at a zero cell, like ``[>]`` or ``[<<]``.
+    /// Fails like the moves of the loop would, e.g. if there is no zero cell left of the memory pointer.
+    /// Returns ``false`` if it would never find a zero cell, which can only happen with a wrapping tape.
+    pub fn scan(&mut self, stride: isize) -> Result<bool, RuntimeErrorKind> {
+        let zero = C::default();
+        // on a wrapping tape the memory pointer visits the same cells again after ``cells.len()`` moves
+        let mut moves = 0;
+        while self.cells[self.mp] != zero {
+            if self.mode == TapeMode::Wrap && moves > self.cells.len() {
+                return Ok(false);
+            }
+
+            match stride {
+                // searching the cells directly is a lot faster than moving one cell at a time
+                1 => match self.cells[self.mp..self.end()]
+                    .iter()
+                    .position(|c| *c == zero)
+                {
+                    Some(i) => self.mp += i,
+                    None => {
+                        // move past the end the same way ``>`` would
+                        moves += self.end() - self.mp;
+                        self.mp = self.end() - 1;
+                        self.move_right(1)?;
+                    }
+                },
+                -1 => match self.cells[self.start..self.mp]
+                    .iter()
+                    .rposition(|c| *c == zero)
+                {
+                    Some(i) => self.mp = self.start + i,
+                    None => {
+                        moves += self.mp - self.start + 1;
+                        self.mp = self.start;
+                        self.move_left(1)?;
+                    }
+                },
+                _ if stride > 0 => self.move_right(stride as usize)?,
+                _ => self.move_left(stride.unsigned_abs())?,
+            }
+            moves += 1;
+        }
+
+        Ok(true)
+    }
+
+    /// The cell the memory pointer points at.
+    #[inline]
+    pub fn cell(&mut self) -> &mut C {
+        &mut self.cells[self.mp]
+    }
+
+    /// Position of the memory pointer relative to the starting cell.
+    pub fn position(&self) -> isize {
+        self.mp as isize - self.origin as isize
+    }
+
+    /// Zeroes the tape, shrinks it back to it's initial size and moves the memory pointer to the starting cell.
+    pub fn reset(&mut self) {
+        self.cells.truncate(self.size);
+        self.cells.fill(C::default());
+        self.cells.resize(self.size, C::default());
+        self.mp = 0;
+        self.origin = 0;
+        self.start = 0;
+        self.reached = self.size;
+    }
+
+    /// Copies up to ``radius`` cells on each side of the memory pointer.
+    pub fn window(&self, radius: usize, signed: bool) -> MemoryWindow {
+        let start = self.mp.saturating_sub(radius);
+        let end = (self.mp + radius + 1).min(self.cells.len());
+
+        MemoryWindow {
+            start: start as isize - self.origin as isize,
+            cells: self.cells[start..end]
+                .iter()
+                .map(|c| c.value(signed))
+                .collect(),
+        }
+    }
+
+    /// Wether the memory pointer can move to the cell at index ``i`` without growing the tape.
+    #[inline]
+    fn is_reached(&self, i: usize) -> bool {
+        // also false for indices that wrapped around below 0
+        i.wrapping_sub(self.start) < self.reached
+    }
+
+    /// Index of the first cell right of the reached cells.
+    #[inline]
+    fn end(&self) -> usize {
+        self.start + self.reached
+    }
+
+    /// Lets the memory pointer reach the cell at index ``mp`` right of the reached cells and returns it's index,
+    /// growing the tape to at least double it's size if needed.
+    /// Only the reached cells count against ``max_size``, the room the tape grew into on the left is given back if needed.
+    #[cold]
+    fn grow_right(&mut self, mut mp: usize) -> Result<usize, RuntimeErrorKind> {
+        if mp + 1 - self.start > self.max_size {
+            return Err(RuntimeErrorKind::OutOfMemory(self.max_size));
+        }
+
+        if mp >= self.cells.len() {
+            if mp >= self.max_size {
+                mp -= self.start;
+                self.cells.drain(..self.start);
+                self.origin -= self.start;
+                self.mp -= self.start;
+                self.start = 0;
+            }
+
+            let size = (self.cells.len() * 2).max(mp + 1).min(self.max_size);
+            self.cells.resize(size, C::default());
+        }
+
+        self.reached = mp + 1 - self.start;
+        Ok(mp)
+    }
+
+    /// Lets the memory pointer reach the cell at index ``mp`` left of the reached cells and returns it's index,
+    /// ``mp`` is negative if it's left of the tape. Adds at least as many cells as the tape has if it grows.
+    /// Only the reached cells count against ``max_size``, the room the tape grew into on the right is given back if needed.
+    #[cold]
+    fn grow_left(&mut self, mp: isize) -> Result<usize, RuntimeErrorKind> {
+        if self.end() as isize - mp > self.max_size as isize {
+            return Err(RuntimeErrorKind::OutOfMemory(self.max_size));
+        }
+
+        let mut mp = mp;
+        if mp < 0 {
+            let missing = mp.unsigned_abs();
+            if self.cells.len() + missing > self.max_size {
+                self.cells.truncate(self.end());
+            }
+
+            let len = self.cells.len();
+            let extra = len.max(missing).min(self.max_size - len);
+            self.cells
+                .splice(0..0, std::iter::repeat_n(C::default(), extra));
+            self.origin += extra;
+            self.mp += extra;
+            self.start += extra;
+            mp += extra as isize;
+        }
+
+        self.reached = self.end() - mp as usize;
+        self.start = mp as usize;
+        Ok(self.start)
+    }
+}
+
+impl FromStr for TapeMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(TapeMode::Error),
+            "wrap" => Ok(TapeMode::Wrap),
+            "infinite" => Ok(TapeMode::Infinite),
+            _ => Err(format!(
+                "unknown tape mode '{}', expected error, wrap or infinite",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for TapeMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TapeMode::Error => write!(f, "error"),
+            TapeMode::Wrap => write!(f, "wrap"),
+            TapeMode::Infinite => write!(f, "infinite"),
+        }
+    }
+}
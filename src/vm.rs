@@ -0,0 +1,1883 @@
+use std::io::{BufRead, BufReader, Read, StdinLock, StdoutLock, Write};
+
+#[cfg(test)]
+use newline_converter::dos2unix;
+
+use crate::{
+    cell::{ArithmeticMode, Cell, CellSize},
+    io::{EofMode, FlushPolicy, Output},
+    ir::{self, Op},
+    optimizer::Pipeline,
+    parser,
+    source::SourceMap,
+    tape::{with_tape, Memory, Tape, TapeMode},
+    BrainfuckError, LoopFrame, RuntimeError, RuntimeErrorKind,
+};
+
+/// Options for the [`VM`]. ``program`` can be anything that implements [`Read`],
+/// for example a [`std::fs::File`], a byte slice or [`std::io::Stdin`].
+#[derive(Debug)]
+pub struct VMOptions<R: Read> {
+    pub program: R,
+    /// Name of the program in error messages, usually it's path
+    pub name: Option<String>,
+    /// Skips every optimization pass, the same as an empty ``pipeline``
+    pub disable_optimizer: bool,
+    /// The optimization passes that run when the program is loaded
+    pub pipeline: Pipeline,
+    pub disable_comments: bool,
+    /// Maximum number of instructions to execute before aborting, ``None`` for no limit
+    pub step_limit: Option<u64>,
+    /// Number of cells the memory starts with, it grows when the memory pointer moves past the end
+    /// (unless ``tape_mode`` is [`TapeMode::Wrap`])
+    pub memory_size: usize,
+    /// Number of cells the memory can grow to before the program is aborted, only cells the memory pointer reached count
+    pub max_memory_size: usize,
+    /// What happens when the memory pointer moves past the ends of the memory
+    pub tape_mode: TapeMode,
+    /// Width of a memory cell
+    pub cell_size: CellSize,
+    /// What happens when ``+`` or ``-`` move a cell past it's maximum or minimum value
+    pub arithmetic: ArithmeticMode,
+    /// Interprets cells as signed integers, which changes the limits of saturating and trapping arithmetic,
+    /// sign extends input and shows negative values in memory dumps. ``[`` and ``]`` still test for zero.
+    pub signed_cells: bool,
+    /// What ``,`` does when there is no input left
+    pub eof: EofMode,
+    /// When the output is flushed, [`FlushPolicy::Line`] by default
+    pub flush: FlushPolicy,
+}
+
+impl<R: Read> VMOptions<R> {
+    /// Creates options for ``program`` with the optimizer and comments enabled.
+    pub fn new(program: R) -> VMOptions<R> {
+        VMOptions {
+            program,
+            name: None,
+            disable_optimizer: false,
+            pipeline: Pipeline::default(),
+            disable_comments: false,
+            step_limit: None,
+            memory_size: 1024,
+            max_memory_size: 1 << 26,
+            tape_mode: TapeMode::Error,
+            cell_size: CellSize::Bits8,
+            arithmetic: ArithmeticMode::Wrapping,
+            signed_cells: false,
+            eof: EofMode::Unchanged,
+            flush: FlushPolicy::Line,
+        }
+    }
+}
+
+#[cfg(test)]
+impl<R: Read> VMOptions<R> {
+    #[cfg(test)]
+    fn default(program: R) -> VMOptions<R> {
+        VMOptions {
+            disable_optimizer: true,
+            ..VMOptions::new(program)
+        }
+    }
+}
+
+/// The brainfuck VM. Reads input for ``,`` from ``I`` and writes the output of ``.`` to ``O``.
+/// [`VM::new`] uses stdin and stdout, [`VM::with_io`] can be used for anything else.
+pub struct VM<I: Read = StdinLock<'static>, O: Write = StdoutLock<'static>> {
+    program: Vec<Op>,
+    source_map: SourceMap,
+    name: String,
+    pp: usize, // ProgramPointer
+    memory: Memory,
+    arithmetic: ArithmeticMode,
+    signed: bool,
+    eof: EofMode,
+    ignore_comments: bool, // wether we should ignore comments (obscure.bf and hell.bf use ';' as non-comment chars)
+    pipeline: Pipeline,
+    iterations: Vec<u64>, // how often the loop starting at every '[' jumped back since it was entered
+    steps: u64,           // number of executed instructions, only counted with a step limit
+    step_limit: Option<u64>,
+    input: BufReader<I>,
+    output: Output<O>,
+}
+
+impl VM {
+    /// Creates a VM that reads from stdin and writes to stdout.
+    pub fn new<R: Read>(options: VMOptions<R>) -> Result<Self, BrainfuckError> {
+        Self::with_io(options, std::io::stdin().lock(), std::io::stdout().lock())
+    }
+
+    /// Creates a VM with the default [`VMOptions`] from a program in a string.
+    pub fn from_source(source: &str) -> Result<Self, BrainfuckError> {
+        Self::from_bytes(source.as_bytes())
+    }
+
+    /// Creates a VM with the default [`VMOptions`] from a program in a byte slice.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BrainfuckError> {
+        Self::new(VMOptions::new(bytes))
+    }
+}
+
+impl<I: Read, O: Write> VM<I, O> {
+    /// Creates a VM that reads from ``input`` and writes to ``output``.
+    pub fn with_io<R: Read>(
+        options: VMOptions<R>,
+        input: I,
+        output: O,
+    ) -> Result<Self, BrainfuckError> {
+        let mut vm = Self {
+            program: Vec::new(),
+            source_map: SourceMap::default(),
+            name: options.name.unwrap_or_else(|| String::from("<program>")),
+            pp: 0,
+            memory: Memory::new(
+                options.cell_size,
+                options.memory_size,
+                options.max_memory_size,
+                options.tape_mode,
+            ),
+            arithmetic: options.arithmetic,
+            signed: options.signed_cells,
+            eof: options.eof,
+            ignore_comments: !options.disable_comments,
+            pipeline: if options.disable_optimizer {
+                Pipeline::level(0)
+            } else {
+                options.pipeline
+            },
+            iterations: Vec::new(),
+            steps: 0,
+            step_limit: options.step_limit,
+            input: BufReader::new(input),
+            output: Output::new(output, options.flush),
+        };
+
+        vm.parse(options.program)?;
+        Ok(vm)
+    }
+
+    /// Maps every instruction of the loaded program to the source code it came from,
+    /// even if the optimizer combined several instructions into one.
+    pub fn source_map(&self) -> &SourceMap {
+        &self.source_map
+    }
+
+    /// Renders the loaded program, after optimizing, as pseudo-assembly (see [`crate::ir::dump`]).
+    pub fn dump_ir(&self) -> String {
+        ir::dump(&self.program, &self.source_map)
+    }
+
+    /// Returns the ProgramPointer, the index of the next instruction to be executed.
+    pub fn pp(&self) -> usize {
+        self.pp
+    }
+
+    /// Returns a reference to the output the program writes to.
+    pub fn output(&self) -> &O {
+        self.output.get_ref()
+    }
+
+    /// Returns a mutable reference to the output the program writes to.
+    pub fn output_mut(&mut self) -> &mut O {
+        self.output.get_mut()
+    }
+
+    /// Consumes the VM and returns it's output, for example to feed it into another VM.
+    pub fn into_output(self) -> O {
+        self.output.into_inner()
+    }
+
+    #[cfg(test)]
+    pub fn load<R: Read>(&mut self, program: R) -> Result<(), BrainfuckError> {
+        self.program.clear();
+        self.parse(program)
+    }
+
+    pub fn reset(&mut self) {
+        self.pp = 0;
+        self.steps = 0;
+        self.iterations.fill(0);
+        self.memory.reset();
+    }
+
+    #[cfg(test)]
+    #[allow(dead_code)]
+    pub fn enable_optimizer<R: Read>(&mut self, program: R) -> Result<(), BrainfuckError> {
+        self.pipeline = Pipeline::default();
+        self.load(program)
+    }
+
+    #[cfg(test)]
+    #[allow(dead_code)]
+    pub fn disable_optimizer<R: Read>(&mut self, program: R) -> Result<(), BrainfuckError> {
+        self.pipeline = Pipeline::level(0);
+        self.load(program)
+    }
+
+    pub fn run(&mut self) -> Result<(), BrainfuckError> {
+        // the memory is moved out of the VM so ``execute`` can borrow both mutably
+        let mut memory = std::mem::take(&mut self.memory);
+        // counting the steps slows every instruction down, so it's only compiled in with a limit
+        let result = with_tape!(&mut memory, tape => match self.step_limit {
+            Some(_) => self.execute::<_, true>(tape),
+            None => self.execute::<_, false>(tape),
+        });
+        self.memory = memory;
+
+        // flush even if the program failed, so it's output isn't lost
+        let flushed = self.output.flush();
+        result?;
+        Ok(flushed?)
+    }
+
+    /// Runs the program on a tape with cells of type ``C``, counting the steps if ``LIMITED``.
+    fn execute<C: Cell, const LIMITED: bool>(
+        &mut self,
+        tape: &mut Tape<C>,
+    ) -> Result<(), BrainfuckError> {
+        let step_limit = self.step_limit.unwrap_or(u64::MAX);
+
+        while self.pp < self.program.len() {
+            if LIMITED {
+                if self.steps == step_limit {
+                    return Err(self.error(RuntimeErrorKind::StepLimitExceeded(step_limit), tape));
+                }
+                self.steps += 1;
+            }
+
+            match self.program[self.pp] {
+                Op::Add(n) => {
+                    // *pointer += n
+                    self.add(tape, tape.mp, n)?;
+                }
+
+                Op::Move(n) => {
+                    // pointer += n
+                    self.move_by(tape, n)?;
+                }
+
+                Op::Out => {
+                    // putchar(*pointer)
+                    self.output.write(tape.cell().to_byte())?;
+                }
+
+                Op::In => {
+                    //getchar(*pointer)
+                    self.read(tape, tape.mp)?;
+                }
+
+                Op::Clear => {
+                    // *pointer = 0
+                    *tape.cell() = C::default();
+                }
+
+                Op::Set(value) => {
+                    // *pointer = value
+                    *tape.cell() = C::from_value(value);
+                }
+
+                Op::MulAdd { offset, factor } => {
+                    // *(pointer + offset) += *pointer * factor
+                    let value = *tape.cell();
+                    if value != C::default() {
+                        self.move_by(tape, offset)?;
+                        *tape.cell() = tape.cell().mul_add(value, factor);
+                        self.move_by(tape, -offset)?;
+                    }
+                }
+
+                Op::Scan(stride) => {
+                    // while *pointer != 0: pointer += stride
+                    if !tape.scan(stride).map_err(|kind| self.error(kind, tape))? {
+                        // the loop never ends, it would only be stopped by the step limit
+                        match self.step_limit {
+                            Some(limit) => {
+                                return Err(
+                                    self.error(RuntimeErrorKind::StepLimitExceeded(limit), tape)
+                                )
+                            }
+                            None => loop {
+                                std::thread::park();
+                            },
+                        }
+                    }
+                }
+
+                Op::Jz(target) => {
+                    // if *pointer == 0: goto end of while
+                    if *tape.cell() == C::default() {
+                        self.pp = target;
+                    }
+                }
+
+                Op::Jnz(target) => {
+                    // } (or "if *pointer != 0: goto start of while")
+                    if *tape.cell() != C::default() {
+                        self.iterations[target] += 1;
+                        self.pp = target;
+                    } else {
+                        // the next time the loop is entered it starts counting again
+                        self.iterations[target] = 0;
+                    }
+                }
+
+                Op::AddAt(offset, n) => {
+                    // *(pointer + offset) += n
+                    let i = self.index(tape, offset)?;
+                    self.add(tape, i, n)?;
+                }
+
+                Op::SetAt(offset, value) => {
+                    // *(pointer + offset) = value
+                    let i = self.index(tape, offset)?;
+                    tape.cells[i] = C::from_value(value);
+                }
+
+                Op::OutAt(offset) => {
+                    // putchar(*(pointer + offset))
+                    let i = self.index(tape, offset)?;
+                    self.output.write(tape.cells[i].to_byte())?;
+                }
+
+                Op::InAt(offset) => {
+                    // getchar(*(pointer + offset))
+                    let i = self.index(tape, offset)?;
+                    self.read(tape, i)?;
+                }
+            }
+
+            // jumps land on the matching bracket, so they continue after it too
+            self.pp += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Reads the next byte of the input, ``None`` if the end of the input was reached.
+    #[inline]
+    fn read_input(&mut self) -> Result<Option<u8>, BrainfuckError> {
+        self.output.before_input()?;
+
+        let byte = match self.input.fill_buf()? {
+            [] => return Ok(None),
+            [byte, ..] => *byte,
+        };
+        self.input.consume(1);
+
+        Ok(Some(byte))
+    }
+
+    /// Moves the memory pointer ``offset`` cells to the right, or to the left if it's negative.
+    #[inline]
+    fn move_by<C: Cell>(&self, tape: &mut Tape<C>, offset: isize) -> Result<(), BrainfuckError> {
+        tape.move_by(offset).map_err(|kind| self.error(kind, tape))
+    }
+
+    /// Returns the index of the cell ``offset`` cells away from the memory pointer.
+    #[inline]
+    fn index<C: Cell>(&self, tape: &mut Tape<C>, offset: isize) -> Result<usize, BrainfuckError> {
+        tape.index(offset).map_err(|kind| self.error(kind, tape))
+    }
+
+    /// Adds ``n`` to the cell at index ``i``, or subtracts it if it's negative.
+    #[inline(always)]
+    fn add<C: Cell>(&self, tape: &mut Tape<C>, i: usize, n: i32) -> Result<(), BrainfuckError> {
+        let cell = tape.cells[i];
+        if self.arithmetic == ArithmeticMode::Wrapping {
+            tape.cells[i] = cell.wrapping_add_signed(n);
+            return Ok(());
+        }
+
+        let (value, kind) = if n < 0 {
+            let value = cell.sub(n.unsigned_abs(), self.arithmetic, self.signed);
+            (value, RuntimeErrorKind::CellUnderflow)
+        } else {
+            let value = cell.add(n as u32, self.arithmetic, self.signed);
+            (value, RuntimeErrorKind::CellOverflow)
+        };
+
+        match value {
+            Some(value) => {
+                tape.cells[i] = value;
+                Ok(())
+            }
+            None => Err(self.error(kind, tape)),
+        }
+    }
+
+    /// Reads a byte of input into the cell at index ``i``, what happens at the end of the input depends on ``eof``.
+    #[inline]
+    fn read<C: Cell>(&mut self, tape: &mut Tape<C>, i: usize) -> Result<(), BrainfuckError> {
+        match self.read_input()? {
+            Some(byte) => tape.cells[i] = C::from_byte(byte, self.signed),
+            None => match self.eof {
+                EofMode::Error => return Err(self.error(RuntimeErrorKind::UnexpectedEof, tape)),
+                EofMode::Unchanged => {}
+                EofMode::Zero => tape.cells[i] = C::default(),
+                EofMode::MinusOne => tape.cells[i] = C::MINUS_ONE,
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Creates a runtime error at the current instruction, including a backtrace of the running loops.
+    #[cold]
+    fn error<C: Cell>(&self, kind: RuntimeErrorKind, tape: &Tape<C>) -> BrainfuckError {
+        RuntimeError {
+            kind,
+            pp: self.pp,
+            mp: tape.position(),
+            location: self.source_map.snippet(self.pp),
+            backtrace: self.backtrace(),
+            memory: tape.window(4, self.signed),
+        }
+        .into()
+    }
+
+    /// The loops around the current instruction, innermost first.
+    /// They aren't tracked while running, they are the ``[`` before it whose ``]`` comes after it.
+    fn backtrace(&self) -> Vec<LoopFrame> {
+        let mut backtrace = Vec::new();
+        let mut pp = self.pp;
+        while pp > 0 {
+            pp -= 1;
+            match self.program[pp] {
+                // a loop that ended before the current instruction, skip over it
+                Op::Jnz(target) => pp = target,
+                Op::Jz(target) if target >= self.pp => backtrace.push(LoopFrame {
+                    pp,
+                    location: self.source_map.snippet(pp),
+                    iteration: self.iterations[pp] + 1,
+                }),
+                _ => {}
+            }
+        }
+
+        backtrace
+    }
+
+    /// Parses the program and checks it for errors
+    fn parse<R: Read>(&mut self, program: R) -> Result<(), BrainfuckError> {
+        let mut parsed_program = parser::parse(program, &self.name, self.ignore_comments)?;
+
+        parsed_program = self.pipeline.run(parsed_program, self.arithmetic);
+        // the passes move instructions around
+        parser::link(&mut parsed_program.ops).ok_or(BrainfuckError::Internal(
+            "Brackets mismatched after optimizing",
+        ))?;
+
+        self.program = parsed_program.ops;
+        self.source_map = parsed_program.source_map;
+        self.iterations = vec![0; self.program.len()];
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{optimizer::Pass, DiagnosticKind};
+    use std::fs::File;
+
+    type TestVM = VM<&'static [u8], Vec<u8>>;
+
+    fn test_vm<R: Read>(options: VMOptions<R>) -> TestVM {
+        VM::with_io(options, &[][..], Vec::new()).unwrap()
+    }
+
+    fn output(vm: &TestVM) -> String {
+        vm.output().iter().map(|c| *c as char).collect()
+    }
+
+    fn tape(vm: &TestVM) -> &Tape<u8> {
+        match &vm.memory {
+            Memory::U8(tape) => tape,
+            _ => panic!("expected 8 bit cells"),
+        }
+    }
+
+    #[test]
+    fn reset() {
+        let program = File::open("./brainfuck_programs/hello_world_smol.bf").unwrap();
+        let mut vm = test_vm(VMOptions::default(program));
+        let program_pre_reset = vm.program.clone();
+
+        vm.run().unwrap();
+
+        assert_eq!("hello world", output(&vm));
+
+        vm.reset();
+        assert_eq!(program_pre_reset, vm.program);
+        assert_eq!(0, vm.pp);
+
+        let mut zerod = true;
+        for b in &tape(&vm).cells {
+            if *b != 0 {
+                zerod = false;
+            }
+        }
+        assert!(zerod);
+    }
+
+    #[test]
+    fn reset_reuse() {
+        let program = File::open("./brainfuck_programs/hello_world_smol.bf").unwrap();
+        let mut vm = test_vm(VMOptions::default(program));
+        vm.run().unwrap();
+
+        assert_eq!("hello world", output(&vm));
+
+        vm.reset();
+        let program = File::open("./brainfuck_programs/yapi_4.bf").unwrap();
+        vm.load(program).unwrap();
+        vm.run().unwrap();
+    }
+
+    #[test]
+    fn no_program() {
+        let mut vm = test_vm(VMOptions::default(
+            File::open("./brainfuck_programs/empty.bf").unwrap(),
+        ));
+        vm.run().unwrap();
+
+        assert!(vm.output().is_empty() && vm.pp == 0 && tape(&vm).mp == 0);
+
+        let mut zerod = true;
+        for b in &tape(&vm).cells {
+            if *b != 0 {
+                zerod = false;
+            }
+        }
+        assert!(zerod);
+    }
+
+    #[test]
+    fn layered_brackets() {
+        let program = File::open("./brainfuck_programs/layeredBracketsTest.bf").unwrap();
+        let mut vm = test_vm(VMOptions::default(program));
+        vm.run().unwrap();
+    }
+
+    #[test]
+    fn deeply_nested_brackets() {
+        let depth = 100_000;
+        let program = "[".repeat(depth) + &"]".repeat(depth) + "+[-]";
+        let mut vm = test_vm(VMOptions::default(program.as_bytes()));
+        vm.run().unwrap();
+
+        assert_eq!(Op::Jz(2 * depth - 1), vm.program[0]);
+        assert_eq!(Op::Jnz(0), vm.program[2 * depth - 1]);
+        assert_eq!(Op::Jz(depth), vm.program[depth - 1]);
+        assert_eq!(Op::Jz(2 * depth + 3), vm.program[2 * depth + 1]);
+    }
+
+    #[test]
+    fn open_ended_while() {
+        let program = File::open("./brainfuck_programs/openEndedWhile.bf").unwrap();
+        let result = VM::with_io(VMOptions::default(program), &[][..], Vec::new());
+
+        let diagnostics = match result {
+            Err(BrainfuckError::Parse(e)) => e.diagnostics,
+            _ => panic!("expected a parse error"),
+        };
+        assert_eq!(1, diagnostics.len());
+        assert_eq!(DiagnosticKind::UnclosedBracket, diagnostics[0].kind);
+        assert_eq!(
+            (1, 5),
+            (diagnostics[0].snippet.line, diagnostics[0].snippet.column)
+        );
+        assert_eq!(None, diagnostics[0].innermost);
+    }
+
+    #[test]
+    fn headless_while() {
+        let program = File::open("./brainfuck_programs/headlessWhile.bf").unwrap();
+        let result = VM::with_io(VMOptions::default(program), &[][..], Vec::new());
+
+        let diagnostics = match result {
+            Err(BrainfuckError::Parse(e)) => e.diagnostics,
+            _ => panic!("expected a parse error"),
+        };
+        assert_eq!(1, diagnostics.len());
+        assert_eq!(DiagnosticKind::UnmatchedClosingBracket, diagnostics[0].kind);
+        assert_eq!(
+            (1, 5),
+            (diagnostics[0].snippet.line, diagnostics[0].snippet.column)
+        );
+    }
+
+    #[test]
+    fn bracket_diagnostics() {
+        let program = "+[ comment\n\t->[+\n]]]\n".as_bytes();
+        let options = VMOptions {
+            name: Some(String::from("test.bf")),
+            ..VMOptions::default(program)
+        };
+        let error = match VM::with_io(options, &[][..], Vec::new()) {
+            Err(e) => e.to_string(),
+            Ok(_) => panic!("expected a parse error"),
+        };
+
+        let expected = "\
+error: unmatched closing bracket `]`
+ --> test.bf:3:3
+  |
+3 | ]]]
+  |   ^ no matching `[` before this";
+        assert_eq!(expected, error);
+
+        let program = "[[\n[]".as_bytes();
+        let options = VMOptions {
+            name: Some(String::from("test.bf")),
+            ..VMOptions::default(program)
+        };
+        let error = match VM::with_io(options, &[][..], Vec::new()) {
+            Err(e) => e.to_string(),
+            Ok(_) => panic!("expected a parse error"),
+        };
+
+        let expected = "\
+error: unclosed bracket `[`
+ --> test.bf:1:1
+  |
+1 | [[
+  | ^ this `[` is never closed
+note: the innermost unclosed `[` is here
+ --> test.bf:1:2
+  |
+1 | [[
+  |  ^
+
+error: unclosed bracket `[`
+ --> test.bf:1:2
+  |
+1 | [[
+  |  ^ this `[` is never closed";
+        assert_eq!(expected, error);
+    }
+
+    #[test]
+    fn mem_pointer_underflow() {
+        let program = File::open("./brainfuck_programs/underflowMP.bf").unwrap();
+        let mut vm = test_vm(VMOptions::default(program));
+
+        assert!(matches!(
+            vm.run(),
+            Err(BrainfuckError::Runtime(e)) if e.kind == RuntimeErrorKind::MemoryUnderflow
+        ));
+    }
+
+    #[test]
+    fn mem_pointer_overflow() {
+        let program = File::open("./brainfuck_programs/overflowMP.bf").unwrap();
+        let mut vm = test_vm(VMOptions::default(program));
+        vm.run().unwrap();
+
+        assert_eq!(1025, tape(&vm).mp);
+        assert_eq!(2048, tape(&vm).cells.len());
+    }
+
+    #[test]
+    fn out_of_memory() {
+        let program = File::open("./brainfuck_programs/overflowMP.bf").unwrap();
+        let options = VMOptions {
+            memory_size: 16,
+            max_memory_size: 1024,
+            ..VMOptions::default(program)
+        };
+        let mut vm = test_vm(options);
+
+        assert!(matches!(
+            vm.run(),
+            Err(BrainfuckError::Runtime(e)) if e.kind == RuntimeErrorKind::OutOfMemory(1024)
+        ));
+        assert_eq!(1023, tape(&vm).mp);
+        assert_eq!(1024, tape(&vm).cells.len());
+    }
+
+    #[test]
+    fn backtrace() {
+        // the + keeps the inner loop from being optimized into a scan
+        let program = "++[>+++[>+<-]>[<<<+]\n<]".as_bytes();
+        let options = VMOptions {
+            name: Some(String::from("test.bf")),
+            ..VMOptions::new(program)
+        };
+        let mut vm = test_vm(options);
+
+        let error = match vm.run() {
+            Err(BrainfuckError::Runtime(e)) => e,
+            _ => panic!("expected a runtime error"),
+        };
+        assert_eq!(RuntimeErrorKind::MemoryUnderflow, error.kind);
+        assert_eq!(2, error.backtrace.len());
+        assert_eq!(
+            Some(15),
+            error.backtrace[0].location.as_ref().map(|l| l.column)
+        );
+        assert_eq!(
+            Some(3),
+            error.backtrace[1].location.as_ref().map(|l| l.column)
+        );
+
+        let expected = "\
+error: memory pointer underflow
+ --> test.bf:1:16
+  |
+1 | ++[>+++[>+<-]>[<<<+]
+  |                ^^^^ moved the memory pointer left of the first cell
+loop backtrace (innermost first):
+   0: [ at test.bf:1:15, iteration 1
+   1: [ at test.bf:1:3, iteration 1
+memory pointer: 2
+    cell 0: 2
+    cell 1: 0
+  > cell 2: 3
+    cell 3: 0
+    cell 4: 0
+    cell 5: 0
+    cell 6: 0";
+        assert_eq!(expected, error.to_string());
+    }
+
+    #[test]
+    fn backtrace_iterations() {
+        // stops at the inner ] in the second iteration of the outer loop, the inner loop was entered again
+        let program = "++[>+++[-]<-]".as_bytes();
+        let options = VMOptions {
+            step_limit: Some(25),
+            disable_optimizer: true,
+            ..VMOptions::new(program)
+        };
+        let mut vm = test_vm(options);
+
+        let error = match vm.run() {
+            Err(BrainfuckError::Runtime(e)) => e,
+            _ => panic!("expected a runtime error"),
+        };
+        assert_eq!(9, error.pp);
+        let frames: Vec<(usize, u64)> = error
+            .backtrace
+            .iter()
+            .map(|frame| (frame.pp, frame.iteration))
+            .collect();
+        assert_eq!(vec![(7, 2), (2, 2)], frames);
+    }
+
+    #[test]
+    fn tape_wrap() {
+        let options = VMOptions {
+            memory_size: 8,
+            tape_mode: TapeMode::Wrap,
+            ..VMOptions::default("+<++<+++>>>>>>>>>>".as_bytes())
+        };
+        let mut vm = test_vm(options);
+        vm.run().unwrap();
+
+        assert_eq!(vec![1, 0, 0, 0, 0, 0, 3, 2], tape(&vm).cells);
+        assert_eq!(0, tape(&vm).mp);
+
+        // the folded instructions have to wrap the same way
+        let options = VMOptions {
+            memory_size: 8,
+            tape_mode: TapeMode::Wrap,
+            ..VMOptions::new("+<<<<<<<<<<<<<<<<<<<++>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>".as_bytes())
+        };
+        let mut vm = test_vm(options);
+        vm.run().unwrap();
+
+        assert_eq!(vec![1, 0, 0, 0, 0, 2, 0, 0], tape(&vm).cells);
+        assert_eq!(1, tape(&vm).mp);
+    }
+
+    #[test]
+    fn tape_infinite() {
+        let options = VMOptions {
+            memory_size: 4,
+            tape_mode: TapeMode::Infinite,
+            ..VMOptions::new("+<<<<<<++>>>>>>>>>>>-<<<<[<]<".as_bytes())
+        };
+        let mut vm = test_vm(options);
+        vm.run().unwrap();
+
+        assert_eq!(0, tape(&vm).position());
+        assert_eq!(20, tape(&vm).cells.len());
+
+        let mut cells = tape(&vm).window(20, false).cells;
+        cells.retain(|c| *c != 0);
+        assert_eq!(vec![2, 1, 255], cells);
+
+        let options = VMOptions {
+            memory_size: 4,
+            max_memory_size: 8,
+            tape_mode: TapeMode::Infinite,
+            ..VMOptions::new("<<<<<".as_bytes())
+        };
+        let mut vm = test_vm(options);
+
+        assert!(matches!(
+            vm.run(),
+            Err(BrainfuckError::Runtime(e)) if e.kind == RuntimeErrorKind::OutOfMemory(8)
+        ));
+
+        // only the cells the memory pointer reached count against the limit, not the room the tape grew into
+        for (program, ok) in [("<<<<<>>>>>>>>>>>+.", true), ("<<<<<>>>>>>>>>>>>+.", false)] {
+            for pipeline in [Pipeline::level(0), Pipeline::default()] {
+                let options = VMOptions {
+                    memory_size: 4,
+                    max_memory_size: 12,
+                    tape_mode: TapeMode::Infinite,
+                    pipeline,
+                    ..VMOptions::new(program.as_bytes())
+                };
+                let mut vm = test_vm(options);
+
+                match vm.run() {
+                    Ok(()) => assert!(ok, "{}", program),
+                    Err(BrainfuckError::Runtime(e)) => {
+                        assert!(!ok, "{}", program);
+                        assert_eq!(RuntimeErrorKind::OutOfMemory(12), e.kind);
+                    }
+                    Err(e) => panic!("unexpected error {}", e),
+                }
+                assert!(tape(&vm).cells.len() <= 12);
+            }
+        }
+    }
+
+    #[test]
+    fn comment_semicolon_ignored() {
+        let program = File::open("./brainfuck_programs/comments_ignored_semicolon.bf").unwrap();
+        let mut vm = test_vm(VMOptions::default(program));
+        vm.run().unwrap();
+
+        assert_eq!("!", output(&vm));
+    }
+
+    #[test]
+    fn comment_poundsign_ignored() {
+        let program = File::open("./brainfuck_programs/comments_ignored_poundsign.bf").unwrap();
+        let mut vm = test_vm(VMOptions::default(program));
+        vm.run().unwrap();
+
+        assert_eq!("!", output(&vm));
+    }
+
+    #[test]
+    fn comment_semipound_ignored() {
+        let program = File::open("./brainfuck_programs/comments_ignored_semipound.bf").unwrap();
+        let mut vm = test_vm(VMOptions::default(program));
+        vm.run().unwrap();
+
+        assert_eq!("!", output(&vm));
+    }
+
+    #[test]
+    fn comment_not_ignored() {
+        let program = &include_bytes!("../brainfuck_programs/comments_ignored_semipound.bf")[..];
+        let options = VMOptions {
+            disable_comments: true,
+            disable_optimizer: true,
+            ..VMOptions::new(program)
+        };
+        let mut vm = test_vm(options);
+        vm.run().unwrap();
+
+        assert_ne!("!", output(&vm));
+    }
+
+    #[test]
+    fn from_source() {
+        let vm = VM::from_source("+[-]>., comment <").unwrap();
+
+        // [-] is optimized into a single instruction
+        let expected = vec![Op::Add(1), Op::Clear, Op::OutAt(1), Op::InAt(1)];
+        assert_eq!(expected, vm.program);
+    }
+
+    #[test]
+    fn from_bytes() {
+        let vm =
+            VM::from_bytes(include_bytes!("../brainfuck_programs/hello_world_smol.bf")).unwrap();
+        let program = File::open("./brainfuck_programs/hello_world_smol.bf").unwrap();
+        let expected = test_vm(VMOptions::new(program));
+
+        assert_eq!(expected.program, vm.program);
+    }
+
+    #[test]
+    fn with_io() {
+        let program = ",.>,.";
+        let mut vm = VM::with_io(
+            VMOptions::default(program.as_bytes()),
+            &b"ab\ncd\n"[..],
+            Vec::new(),
+        )
+        .unwrap();
+        vm.run().unwrap();
+
+        assert_eq!(b"ab", &vm.into_output()[..]);
+    }
+
+    #[test]
+    fn rot13() {
+        let program = File::open("./brainfuck_programs/rot13.bf").unwrap();
+        let options = VMOptions {
+            eof: EofMode::Unchanged,
+            ..VMOptions::new(program)
+        };
+        let mut vm = VM::with_io(options, &b"Hello, World!\n\xff"[..], Vec::new()).unwrap();
+        vm.run().unwrap();
+
+        assert_eq!(b"Uryyb, Jbeyq!\n\xff", &vm.into_output()[..]);
+    }
+
+    #[test]
+    fn wc() {
+        let program = File::open("./brainfuck_programs/wc.bf").unwrap();
+        let options = VMOptions {
+            eof: EofMode::Zero,
+            ..VMOptions::new(program)
+        };
+        let mut vm = VM::with_io(options, &b"one two\nthree \xc3\x28\n"[..], Vec::new()).unwrap();
+        vm.run().unwrap();
+
+        assert_eq!(b"\t2\t4\t17\n", &vm.into_output()[..]);
+    }
+
+    #[test]
+    fn eof_default() {
+        // the defaults are the same as the CLI's, programs that stop at the end of input run without --eof
+        let programs: [(&str, &[u8], &[u8]); 2] = [
+            ("rot13.bf", b"Hello\n", b"Uryyb\n"),
+            ("wc.bf", b"one two\nthree\n", b"\t2\t3\t14\n"),
+        ];
+        for (name, input, expected) in programs {
+            let program = File::open(format!("./brainfuck_programs/{}", name)).unwrap();
+            let mut vm = VM::with_io(VMOptions::new(program), input, Vec::new()).unwrap();
+            vm.run().unwrap();
+
+            assert_eq!(expected, &vm.into_output()[..], "{}", name);
+        }
+    }
+
+    #[test]
+    fn flush_policies() {
+        /// Remembers how much output it had received every time it was flushed.
+        #[derive(Default)]
+        struct Flushes {
+            data: Vec<u8>,
+            flushes: Vec<usize>,
+        }
+
+        impl Write for Flushes {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.data.write(buf)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                self.flushes.push(self.data.len());
+                Ok(())
+            }
+        }
+
+        let expected = [
+            // flushing before input and at the end flushes the writer again, even though nothing was written
+            (FlushPolicy::Byte, vec![1, 2, 3, 3, 4, 4]),
+            (FlushPolicy::Line, vec![2, 3, 4]),
+            (FlushPolicy::Input, vec![3, 4]),
+            (FlushPolicy::Full, vec![4]),
+        ];
+
+        for (flush, flushes) in expected {
+            let options = VMOptions {
+                flush,
+                ..VMOptions::new("++++++++++[>++++++++++<-]>---.>++++++++++.<.,.".as_bytes())
+            };
+            let mut vm = VM::with_io(options, "b".as_bytes(), Flushes::default()).unwrap();
+            vm.run().unwrap();
+
+            let output = vm.into_output();
+            assert_eq!(b"a\nab", &output.data[..]);
+            assert_eq!(flushes, output.flushes, "{}", flush);
+        }
+
+        // the output is flushed when the program fails
+        let options = VMOptions {
+            flush: FlushPolicy::Full,
+            ..VMOptions::new("+.<".as_bytes())
+        };
+        let mut vm = VM::with_io(options, "".as_bytes(), Flushes::default()).unwrap();
+        assert!(vm.run().is_err());
+        assert_eq!(vec![1], vm.output().flushes);
+
+        // a buffer that was written because it was full still gets flushed at the end
+        let program = ".".repeat(Output::<Flushes>::CAPACITY);
+        for flush in [FlushPolicy::Line, FlushPolicy::Input, FlushPolicy::Full] {
+            let options = VMOptions {
+                flush,
+                ..VMOptions::new(program.as_bytes())
+            };
+            let mut vm = VM::with_io(options, "".as_bytes(), Flushes::default()).unwrap();
+            vm.run().unwrap();
+            assert_eq!(
+                vec![Output::<Flushes>::CAPACITY],
+                vm.output().flushes,
+                "{}",
+                flush
+            );
+        }
+    }
+
+    #[test]
+    fn pipe_vms() {
+        let program = File::open("./brainfuck_programs/hello_world_smol.bf").unwrap();
+        let mut writer = test_vm(VMOptions::default(program));
+        writer.run().unwrap();
+
+        let input = writer.into_output();
+        let mut reader =
+            VM::with_io(VMOptions::default(",.".as_bytes()), &input[..], Vec::new()).unwrap();
+        reader.run().unwrap();
+
+        assert_eq!(b"h", &reader.into_output()[..]);
+    }
+
+    #[test]
+    fn unexpected_eof() {
+        let options = VMOptions {
+            eof: EofMode::Error,
+            ..VMOptions::default(",".as_bytes())
+        };
+        let mut vm = test_vm(options);
+
+        let error = match vm.run() {
+            Err(BrainfuckError::Runtime(e)) => e,
+            _ => panic!("expected a runtime error"),
+        };
+        assert_eq!(RuntimeErrorKind::UnexpectedEof, error.kind);
+        assert_eq!((0, 0), (error.pp, error.mp));
+    }
+
+    #[test]
+    fn eof_modes() {
+        let expected = [
+            (EofMode::Unchanged, CellSize::Bits8, 5),
+            (EofMode::Zero, CellSize::Bits8, 0),
+            (EofMode::MinusOne, CellSize::Bits8, 255),
+            (EofMode::MinusOne, CellSize::Bits16, 65535),
+        ];
+
+        for (eof, cell_size, cell) in expected {
+            let options = VMOptions {
+                eof,
+                cell_size,
+                ..VMOptions::new(",>+++++,".as_bytes())
+            };
+            let mut vm: TestVM = VM::with_io(options, "a".as_bytes(), Vec::new()).unwrap();
+            vm.run().unwrap();
+
+            let cells = match &vm.memory {
+                Memory::U8(tape) => tape.window(1, false).cells,
+                Memory::U16(tape) => tape.window(1, false).cells,
+                _ => unreachable!(),
+            };
+            assert_eq!(vec![97, cell, 0], cells, "{}", eof);
+        }
+    }
+
+    #[test]
+    fn step_limit() {
+        let program = "+[]".as_bytes();
+        let options = VMOptions {
+            step_limit: Some(100),
+            ..VMOptions::new(program)
+        };
+        let mut vm = test_vm(options);
+
+        assert!(matches!(
+            vm.run(),
+            Err(BrainfuckError::Runtime(e)) if e.kind == RuntimeErrorKind::StepLimitExceeded(100)
+        ));
+        assert_eq!(100, vm.steps);
+    }
+
+    #[test]
+    fn saturating_arithmetic() {
+        let underflow = "----------------------------------------+>".repeat(2);
+        let overflow = "+".repeat(300);
+
+        for disable_optimizer in [true, false] {
+            let options = VMOptions {
+                arithmetic: ArithmeticMode::Saturating,
+                disable_optimizer,
+                ..VMOptions::new(underflow.as_bytes())
+            };
+            let mut vm = test_vm(options);
+            vm.run().unwrap();
+            assert_eq!(vec![1, 1], tape(&vm).cells[..2]);
+
+            let options = VMOptions {
+                arithmetic: ArithmeticMode::Saturating,
+                disable_optimizer,
+                ..VMOptions::new(overflow.as_bytes())
+            };
+            let mut vm = test_vm(options);
+            vm.run().unwrap();
+            assert_eq!(255, tape(&vm).cells[0]);
+        }
+    }
+
+    #[test]
+    fn trapping_arithmetic() {
+        let overflow = "+".repeat(256);
+        let options = VMOptions {
+            arithmetic: ArithmeticMode::Trapping,
+            ..VMOptions::default(overflow.as_bytes())
+        };
+        let mut vm = test_vm(options);
+
+        match vm.run() {
+            Err(BrainfuckError::Runtime(e)) => {
+                assert_eq!(RuntimeErrorKind::CellOverflow, e.kind);
+                assert_eq!(255, e.pp);
+                assert_eq!(vec![255], e.memory.cells[..1]);
+            }
+            other => panic!("expected a runtime error, got {:?}", other.err()),
+        }
+
+        // the folded instructions have to trap too, pointing at all of the instructions they replace
+        let options = VMOptions {
+            name: Some(String::from("trap.bf")),
+            arithmetic: ArithmeticMode::Trapping,
+            ..VMOptions::new("+++[-]\n  --------".as_bytes())
+        };
+        let mut vm = test_vm(options);
+        let error = vm.run().unwrap_err();
+
+        let expected = "error: cell underflow
+ --> trap.bf:2:3
+  |
+2 |   --------
+  |   ^^^^^^^^ decremented the cell below it's minimum value
+memory pointer: 0
+  > cell 0: 0
+    cell 1: 0
+    cell 2: 0
+    cell 3: 0
+    cell 4: 0";
+        assert_eq!(expected, error.to_string());
+
+        // wider cells trap at their own maximum
+        let options = VMOptions {
+            arithmetic: ArithmeticMode::Trapping,
+            cell_size: CellSize::Bits16,
+            ..VMOptions::new(overflow.as_bytes())
+        };
+        let mut vm = test_vm(options);
+        vm.run().unwrap();
+    }
+
+    #[test]
+    fn last_char_is_plus() {
+        let program = File::open("./brainfuck_programs/ends_on_plus.bf").unwrap();
+        let mut vm = test_vm(VMOptions::default(program));
+        let program = File::open("./brainfuck_programs/ends_on_plus.bf").unwrap();
+        vm.enable_optimizer(program).unwrap();
+        vm.run().unwrap();
+    }
+
+    #[test]
+    fn optimizer() {
+        let program = File::open("./brainfuck_programs/optimize_me.bf").unwrap();
+        let options = VMOptions::new(program);
+        let mut vm = test_vm(options);
+        vm.run().unwrap();
+        // the moves are fused into the instructions, except where the memory pointer turns around without using a cell
+        let optimized_program = vec![
+            Op::Add(5),
+            Op::Add(-5),
+            Op::Move(5),
+            Op::AddAt(-5, 1),
+            Op::AddAt(-5, -1),
+            Op::AddAt(-5, 5),
+            Op::AddAt(-5, -5),
+            Op::AddAt(-2, 1),
+            Op::Add(-2),
+            Op::AddAt(-1, -2),
+            Op::Move(-1),
+        ];
+        assert_eq!(optimized_program, vm.program);
+    }
+
+    #[test]
+    fn clear_loops() {
+        let program = "+++[-]>++[---]>+[+]++++>-[--]<<<<+[-]".to_owned() + &"-".repeat(300);
+        let vm = test_vm(VMOptions::new(program.as_bytes()));
+        let optimized_program = vec![
+            Op::Add(3),
+            Op::Clear,
+            Op::AddAt(1, 2),
+            Op::SetAt(1, 0),
+            Op::AddAt(2, 1),
+            Op::SetAt(2, 4),
+            Op::AddAt(3, -1),
+            Op::Move(3),
+            Op::Jz(10),
+            Op::Add(-2),
+            Op::Jnz(8),
+            Op::AddAt(-4, 1),
+            Op::SetAt(-4, -300),
+            Op::Move(-4),
+        ];
+        assert_eq!(optimized_program, vm.program);
+        assert_eq!(16..23, vm.source_map().span(5).unwrap().range()); // [+]++++
+
+        // the optimized program has to leave the memory in the same state
+        for cell_size in [CellSize::Bits8, CellSize::Bits16] {
+            let mut cells = Vec::new();
+            for disable_optimizer in [true, false] {
+                let options = VMOptions {
+                    cell_size,
+                    disable_optimizer,
+                    ..VMOptions::new("+++[-]>++[---]>+[+]++++<<+[-]---".as_bytes())
+                };
+                let mut vm = test_vm(options);
+                vm.run().unwrap();
+                cells.push(with_tape!(&vm.memory, tape => tape.window(4, false)));
+            }
+            assert_eq!(cells[0], cells[1], "{} bit cells", cell_size);
+        }
+
+        // [-] doesn't clear negative cells with saturating arithmetic
+        let options = VMOptions {
+            arithmetic: ArithmeticMode::Saturating,
+            ..VMOptions::new("+[-]".as_bytes())
+        };
+        assert_eq!(
+            vec![Op::Add(1), Op::Jz(3), Op::Add(-1), Op::Jnz(1)],
+            test_vm(options).program
+        );
+    }
+
+    #[test]
+    fn multiply_loops() {
+        let vm = test_vm(VMOptions::new("+++[->++>>-<<<]>[>+<-<+>]".as_bytes()));
+        let optimized_program = vec![
+            Op::Add(3),
+            Op::MulAdd {
+                offset: 1,
+                factor: 2,
+            },
+            Op::MulAdd {
+                offset: 3,
+                factor: -1,
+            },
+            Op::Clear,
+            Op::Move(1),
+            Op::MulAdd {
+                offset: 1,
+                factor: 1,
+            },
+            Op::MulAdd {
+                offset: -1,
+                factor: 1,
+            },
+            Op::Clear,
+        ];
+        assert_eq!(optimized_program, vm.program);
+        assert_eq!(3..15, vm.source_map().span(1).unwrap().range());
+
+        // loops that don't decrement by one or don't end at the cell they started at are kept
+        for program in ["+[-->+<]", "+[->+]", "+[->+<.]", "+[->[-]<]"] {
+            let vm = test_vm(VMOptions::new(program.as_bytes()));
+            assert!(matches!(vm.program[1], Op::Jz(_)), "{}", program);
+        }
+
+        // multiplying is skipped if the cell is 0, so the memory pointer doesn't underflow
+        let mut vm = test_vm(VMOptions::new(">[<->-]".as_bytes()));
+        vm.run().unwrap();
+        let mut vm = test_vm(VMOptions::new("+[<+>-]".as_bytes()));
+        assert!(matches!(
+            vm.run(),
+            Err(BrainfuckError::Runtime(e)) if e.kind == RuntimeErrorKind::MemoryUnderflow
+        ));
+
+        // loops that move past the cells they change are kept, the moves can fail
+        for program in ["+[<>-]", "+[>>+<<<>-]"] {
+            let mut vm = test_vm(VMOptions::new(program.as_bytes()));
+            assert!(matches!(vm.program[1], Op::Jz(_)), "{}", program);
+            assert!(matches!(
+                vm.run(),
+                Err(BrainfuckError::Runtime(e)) if e.kind == RuntimeErrorKind::MemoryUnderflow
+            ));
+        }
+        let options = VMOptions {
+            memory_size: 4,
+            max_memory_size: 4,
+            ..VMOptions::new("+[>>>>>+-<<<<<-]+.".as_bytes())
+        };
+        assert!(matches!(
+            test_vm(options).run(),
+            Err(BrainfuckError::Runtime(e)) if e.kind == RuntimeErrorKind::OutOfMemory(4)
+        ));
+    }
+
+    #[test]
+    fn scan_loops() {
+        // without dead code elimination, only the first loop could ever run
+        let mut pipeline = Pipeline::default();
+        pipeline.disable(Pass::DeadCode);
+        let options = VMOptions {
+            pipeline,
+            ..VMOptions::new("[>][<][>>>][-]".as_bytes())
+        };
+        let vm = test_vm(options);
+        let optimized_program = vec![Op::Scan(1), Op::Scan(-1), Op::Scan(3), Op::Clear];
+        assert_eq!(optimized_program, vm.program);
+        assert_eq!(3..6, vm.source_map().span(1).unwrap().range()); // [<]
+
+        // scanning has to end up at the same cell (or fail the same way) for every tape mode
+        let programs = [
+            "+>+>+>>+<<<<[>]+",
+            "+>+>+[<]+",
+            "+>+>+>+<[<]+",
+            "+>>+>>+<<<<[>>]+",
+            ">+>+>+[<<]+",
+            "+>+>+>+>+>+>+>+[>]+",
+        ];
+        for tape_mode in [TapeMode::Error, TapeMode::Wrap, TapeMode::Infinite] {
+            for program in programs {
+                let mut results = Vec::new();
+                for disable_optimizer in [true, false] {
+                    let options = VMOptions {
+                        memory_size: 8,
+                        max_memory_size: 8,
+                        tape_mode,
+                        disable_optimizer,
+                        // without a zero cell the scan never ends on a wrapping tape
+                        step_limit: Some(1000),
+                        ..VMOptions::new(program.as_bytes())
+                    };
+                    let mut vm = test_vm(options);
+                    // folded moves fail before moving, so the position is only compared if there's no error
+                    let result = match vm.run() {
+                        Ok(()) => Ok(tape(&vm).position()),
+                        Err(BrainfuckError::Runtime(e)) => Err(e.kind),
+                        Err(e) => panic!("unexpected error {}", e),
+                    };
+                    results.push((result, tape(&vm).cells.clone()));
+                }
+                assert_eq!(
+                    results[0], results[1],
+                    "{} with {} tape",
+                    program, tape_mode
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn offset_fusion() {
+        let vm = test_vm(VMOptions::new(">+>++<<-[>.<-]".as_bytes()));
+        let optimized_program = vec![
+            Op::AddAt(1, 1),
+            Op::AddAt(2, 2),
+            Op::Add(-1),
+            Op::Jz(6),
+            Op::OutAt(1),
+            Op::Add(-1),
+            Op::Jnz(3),
+        ];
+        assert_eq!(optimized_program, vm.program);
+        assert_eq!(0..2, vm.source_map().span(0).unwrap().range()); // >+
+
+        // the fused program has to fail the same way and leave the memory in the same state for every tape mode
+        let programs = [
+            "<>+",
+            "+>>.<<<+",
+            "+<<<+>>>>+",
+            ">>>>>>>>>>+<<<<<<<<<<",
+            "+>+>+<.<[>]",
+        ];
+        for tape_mode in [TapeMode::Error, TapeMode::Wrap, TapeMode::Infinite] {
+            for program in programs {
+                let mut results = Vec::new();
+                for disable_optimizer in [true, false] {
+                    let options = VMOptions {
+                        memory_size: 8,
+                        max_memory_size: 8,
+                        tape_mode,
+                        disable_optimizer,
+                        ..VMOptions::new(program.as_bytes())
+                    };
+                    let mut vm = test_vm(options);
+                    let result = match vm.run() {
+                        Ok(()) => Ok(tape(&vm).position()),
+                        Err(BrainfuckError::Runtime(e)) => Err(e.kind),
+                        Err(e) => panic!("unexpected error {}", e),
+                    };
+                    results.push((result, tape(&vm).cells.clone(), output(&vm)));
+                }
+                assert_eq!(
+                    results[0], results[1],
+                    "{} with {} tape",
+                    program, tape_mode
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn dead_loops() {
+        let program = "[comment, with. instructions][-]+[>]>[-][<+>-][.]";
+        let vm = test_vm(VMOptions::new(program.as_bytes()));
+        let optimized_program = vec![Op::Add(1), Op::Scan(1), Op::SetAt(1, 0), Op::Move(1)];
+        assert_eq!(optimized_program, vm.program);
+    }
+
+    #[test]
+    fn optimization_levels() {
+        let program = "+++[->++<]>[>]<.";
+        let levels = [
+            vec![
+                Op::Add(1),
+                Op::Add(1),
+                Op::Add(1),
+                Op::Jz(9),
+                Op::Add(-1),
+                Op::Move(1),
+                Op::Add(1),
+                Op::Add(1),
+                Op::Move(-1),
+                Op::Jnz(3),
+                Op::Move(1),
+                Op::Jz(13),
+                Op::Move(1),
+                Op::Jnz(11),
+                Op::Move(-1),
+                Op::Out,
+            ],
+            vec![
+                Op::Add(3),
+                Op::Jz(6),
+                Op::Add(-1),
+                Op::Move(1),
+                Op::Add(2),
+                Op::Move(-1),
+                Op::Jnz(1),
+                Op::Move(1),
+                Op::Jz(10),
+                Op::Move(1),
+                Op::Jnz(8),
+                Op::Move(-1),
+                Op::Out,
+            ],
+            vec![
+                Op::Add(3),
+                Op::MulAdd {
+                    offset: 1,
+                    factor: 2,
+                },
+                Op::Clear,
+                Op::Move(1),
+                Op::Scan(1),
+                Op::Move(-1),
+                Op::Out,
+            ],
+            vec![
+                Op::Add(3),
+                Op::MulAdd {
+                    offset: 1,
+                    factor: 2,
+                },
+                Op::Clear,
+                Op::Move(1),
+                Op::Scan(1),
+                Op::OutAt(-1),
+                Op::Move(-1),
+            ],
+        ];
+
+        for (level, expected) in levels.into_iter().enumerate() {
+            let options = VMOptions {
+                pipeline: Pipeline::level(level as u8),
+                ..VMOptions::new(program.as_bytes())
+            };
+            let mut vm = test_vm(options);
+            assert_eq!(expected, vm.program, "-O{}", level);
+            vm.run().unwrap();
+            assert_eq!("\x06", output(&vm), "-O{}", level);
+        }
+
+        // single passes can be turned on and off
+        let mut pipeline = Pipeline::level(1);
+        pipeline.enable(Pass::ScanLoops);
+        pipeline.disable(Pass::Fold);
+        let options = VMOptions {
+            pipeline,
+            ..VMOptions::new("++[>]".as_bytes())
+        };
+        let vm = test_vm(options);
+        assert_eq!(vec![Op::Add(1), Op::Add(1), Op::Scan(1)], vm.program);
+
+        // disable_optimizer wins over the pipeline
+        let options = VMOptions {
+            disable_optimizer: true,
+            ..VMOptions::new("++".as_bytes())
+        };
+        assert_eq!(vec![Op::Add(1), Op::Add(1)], test_vm(options).program);
+
+        for pass in Pass::ALL {
+            assert_eq!(Ok(pass), pass.to_string().parse());
+        }
+        assert!("unrolling".parse::<Pass>().is_err());
+    }
+
+    #[test]
+    fn optimized_programs() {
+        // (program, comments, input)
+        let programs = [
+            ("beer.bf", true, ""),
+            ("bench.bf", true, ""),
+            ("factorials.bf", true, ""),
+            ("fibonacci.bf", true, ""),
+            ("hell.bf", false, ""),
+            ("hello_world.bf", true, ""),
+            ("mandel.bf", true, ""),
+            ("obscure.bf", false, ""),
+            ("quine.bf", true, ""),
+            ("rot13.bf", true, "Hello, World!\n"),
+            ("squares.bf", true, ""),
+            ("triangle.bf", true, ""),
+            ("wc.bf", true, "one two\nthree\n"),
+            ("yapi.bf", true, ""),
+        ];
+
+        for (name, comments, input) in programs {
+            let run = |disable_optimizer| {
+                let program = File::open(format!("./brainfuck_programs/{}", name)).unwrap();
+                let options = VMOptions {
+                    disable_optimizer,
+                    disable_comments: !comments,
+                    // some programs never end or take too long without the optimizer
+                    step_limit: Some(20_000_000),
+                    eof: EofMode::Zero,
+                    ..VMOptions::new(program)
+                };
+                let mut vm = VM::with_io(options, input.as_bytes(), Vec::new()).unwrap();
+                let result = vm.run();
+                (result.is_ok(), vm.into_output())
+            };
+
+            let (finished, unoptimized) = run(true);
+            let (optimized_finished, optimized) = run(false);
+            if finished {
+                assert!(optimized_finished, "{}", name);
+                assert_eq!(unoptimized, optimized, "{}", name);
+            } else {
+                // the optimized program gets further in the same number of steps
+                assert!(optimized.starts_with(&unoptimized), "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn source_map() {
+        let program = File::open("./brainfuck_programs/optimize_me.bf").unwrap();
+        let vm = test_vm(VMOptions::new(program));
+        let source_map = vm.source_map();
+        let columns = |pp: usize| {
+            let span = source_map.span(pp).unwrap();
+            (span.start.column, span.end.column)
+        };
+
+        assert_eq!(vm.program.len(), source_map.spans().len());
+        assert_eq!((1, 5), columns(0)); // +++++
+        assert_eq!((6, 10), columns(1)); // -----
+        assert_eq!((22, 22), columns(4)); // -
+        assert_eq!((43, 45), columns(9)); // <-- at the end
+        assert_eq!(42..45, source_map.span(9).unwrap().range());
+        assert_eq!((16, 43), columns(10)); // the moves since the memory pointer turned around
+
+        let long_run = "+".repeat(40);
+        let vm = test_vm(VMOptions::new(long_run.as_bytes()));
+        assert_eq!(vec![Op::Add(40)], vm.program);
+        assert_eq!(0..40, vm.source_map().span(0).unwrap().range());
+    }
+
+    #[test]
+    fn dump_ir() {
+        let options = VMOptions {
+            name: Some(String::from("example.bf")),
+            ..VMOptions::new("+++[>]<.".as_bytes())
+        };
+        let expected = "\
+; example.bf: 4 instructions
+0  add 3     ; 1:1-3  +++ (3)
+1  scan +1   ; 1:4-6  [>] (3)
+2  out [-1]  ; 1:7-8  <. (2)
+3  move -1   ; 1:7    <
+";
+        assert_eq!(expected, test_vm(options).dump_ir());
+
+        let options = VMOptions {
+            name: Some(String::from("example.bf")),
+            ..VMOptions::default("+[\n>-]".as_bytes())
+        };
+        let expected = "\
+; example.bf: 5 instructions
+0  add 1   ; 1:1  +
+1  jz 4    ; 1:2  [
+2  move 1  ; 2:1  >
+3  add -1  ; 2:2  -
+4  jnz 1   ; 2:3  ]
+";
+        assert_eq!(expected, test_vm(options).dump_ir());
+
+        // instructions in comments aren't part of the excerpt
+        let options = VMOptions {
+            name: Some(String::from("example.bf")),
+            ..VMOptions::new("++ ; comment with a + and a . in it\n++.".as_bytes())
+        };
+        let expected = "\
+; example.bf: 2 instructions
+0  add 4  ; 1:1-2:2  ++++ (4)
+1  out    ; 2:3      .
+";
+        assert_eq!(expected, test_vm(options).dump_ir());
+    }
+
+    #[test]
+    fn hello_world() {
+        let program = File::open("./brainfuck_programs/hello_world.bf").unwrap();
+        let mut vm = test_vm(VMOptions::default(program));
+        vm.run().unwrap();
+
+        // Super fucking weird, why tf is it \n\r??? It's from https://de.wikipedia.org/wiki/Brainfuck
+        assert_eq!("Hello World!\n\r", output(&vm));
+    }
+
+    #[test]
+    fn hello_world_smol() {
+        let program = File::open("./brainfuck_programs/hello_world_smol.bf").unwrap();
+        let mut vm = test_vm(VMOptions::default(program));
+        vm.run().unwrap();
+
+        assert_eq!("hello world", output(&vm));
+    }
+
+    #[test]
+    fn hell() {
+        // "Hello world from hell": https://github.com/rdebath/Brainfuck/blob/master/bitwidth.b
+        let program = File::open("./brainfuck_programs/hell.bf").unwrap();
+        let options = VMOptions {
+            disable_comments: true,
+            disable_optimizer: true,
+            ..VMOptions::new(program)
+        };
+        let mut vm = test_vm(options);
+        vm.run().unwrap();
+
+        assert_eq!("Hello World! 255\n", output(&vm));
+
+        vm.reset();
+        vm.output_mut().clear();
+        let program = File::open("./brainfuck_programs/hell.bf").unwrap();
+        vm.enable_optimizer(program).unwrap();
+        vm.run().unwrap();
+
+        assert_eq!("Hello World! 255\n", output(&vm));
+    }
+
+    #[test]
+    fn hell_cell_sizes() {
+        let expected = [
+            (CellSize::Bits16, "Hello world! 65535\n"),
+            (CellSize::Bits32, "Hello, world!\n"),
+            (CellSize::Bits64, "Hello, world!\n"),
+        ];
+
+        for (cell_size, expected) in expected {
+            for disable_optimizer in [true, false] {
+                let program = File::open("./brainfuck_programs/hell.bf").unwrap();
+                let options = VMOptions {
+                    disable_comments: true,
+                    disable_optimizer,
+                    cell_size,
+                    ..VMOptions::new(program)
+                };
+                let mut vm = test_vm(options);
+                vm.run().unwrap();
+
+                assert_eq!(expected, output(&vm), "{} bit cells", cell_size);
+            }
+        }
+    }
+
+    #[test]
+    fn wide_cells() {
+        // cells wrap at their own width and the memory dump shows their full value
+        let options = VMOptions {
+            cell_size: CellSize::Bits16,
+            ..VMOptions::new("-<".as_bytes())
+        };
+        let mut vm = test_vm(options);
+
+        match vm.run() {
+            Err(BrainfuckError::Runtime(e)) => assert_eq!(vec![65535], e.memory.cells[..1]),
+            other => panic!("expected a runtime error, got {:?}", other.err()),
+        }
+
+        // only the lowest 8 bits are written
+        let options = VMOptions {
+            cell_size: CellSize::Bits32,
+            ..VMOptions::new("++++++++[>++++++++++++++++++++++++++++++++<-]>+.".as_bytes())
+        };
+        let mut vm = test_vm(options);
+        vm.run().unwrap();
+
+        assert_eq!("\u{1}", output(&vm));
+    }
+
+    #[test]
+    fn signed_cells() {
+        // memory dumps show negative values, zero tests are unchanged
+        let options = VMOptions {
+            signed_cells: true,
+            ..VMOptions::new("->--[+]-<<".as_bytes())
+        };
+        let mut vm = test_vm(options);
+
+        match vm.run() {
+            Err(BrainfuckError::Runtime(e)) => assert_eq!(vec![-1, -1], e.memory.cells[..2]),
+            other => panic!("expected a runtime error, got {:?}", other.err()),
+        }
+
+        // trapping and saturating arithmetic use the signed limits
+        let overflow = "+".repeat(128);
+        let options = VMOptions {
+            signed_cells: true,
+            arithmetic: ArithmeticMode::Trapping,
+            ..VMOptions::default(overflow.as_bytes())
+        };
+        let mut vm = test_vm(options);
+
+        match vm.run() {
+            Err(BrainfuckError::Runtime(e)) => {
+                assert_eq!(RuntimeErrorKind::CellOverflow, e.kind);
+                assert_eq!(127, e.pp);
+                assert_eq!(vec![127], e.memory.cells[..1]);
+            }
+            other => panic!("expected a runtime error, got {:?}", other.err()),
+        }
+
+        let underflow = "-".repeat(200);
+        let options = VMOptions {
+            signed_cells: true,
+            arithmetic: ArithmeticMode::Saturating,
+            ..VMOptions::new(underflow.as_bytes())
+        };
+        let mut vm = test_vm(options);
+        vm.run().unwrap();
+        assert_eq!(vec![128], tape(&vm).cells[..1]);
+
+        // input is sign extended
+        assert_eq!(0xffff, u16::from_byte(0xff, true));
+        assert_eq!(0x00ff, u16::from_byte(0xff, false));
+        assert_eq!(0x7f, u64::from_byte(0x7f, true));
+    }
+
+    #[test]
+    fn squares() {
+        let program = File::open("./brainfuck_programs/squares.bf").unwrap();
+        let mut vm = test_vm(VMOptions::default(program));
+        vm.run().unwrap();
+
+        let should_be = include_str!("../brainfuck_programs/squares_output_correct.txt");
+        let should_be = dos2unix(should_be).to_string();
+        assert_eq!(should_be, output(&vm));
+    }
+
+    #[test]
+    fn quine() {
+        // Written by Erik Bosman
+        let program = File::open("./brainfuck_programs/quine.bf").unwrap();
+        let program2 = include_str!("../brainfuck_programs/quine.bf");
+        let mut vm = test_vm(VMOptions::default(program));
+        vm.run().unwrap();
+
+        assert_eq!(program2, output(&vm));
+    }
+
+    #[test]
+    fn obscure() {
+        let program = File::open("./brainfuck_programs/obscure.bf").unwrap();
+        let options = VMOptions {
+            disable_comments: true,
+            disable_optimizer: true,
+            ..VMOptions::new(program)
+        };
+        let mut vm = test_vm(options);
+        vm.run().unwrap();
+
+        assert_eq!("H\n", output(&vm));
+
+        vm.reset();
+        vm.output_mut().clear();
+        let program = File::open("./brainfuck_programs/obscure.bf").unwrap();
+        vm.enable_optimizer(program).unwrap();
+        vm.run().unwrap();
+
+        assert_eq!("H\n", output(&vm));
+    }
+
+    #[test]
+    fn fibonacci() {
+        let program = File::open("./brainfuck_programs/fibonacci.bf").unwrap();
+        let mut vm = test_vm(VMOptions::default(program));
+        vm.run().unwrap();
+
+        // yes those are wrong, but that's the programs fault. These numbers are from https://copy.sh/brainfuck which I assume is correct
+        assert_eq!(
+            "1, 1, 2, 3, 5, 8, 13, 21, 34, 55, 89, 144, 233, 121, 98, 219, ...",
+            output(&vm)
+        );
+
+        vm.reset();
+        vm.output_mut().clear();
+        let program = File::open("./brainfuck_programs/fibonacci.bf").unwrap();
+        vm.enable_optimizer(program).unwrap();
+        vm.run().unwrap();
+
+        assert_eq!(
+            "1, 1, 2, 3, 5, 8, 13, 21, 34, 55, 89, 144, 233, 121, 98, 219, ...",
+            output(&vm)
+        );
+    }
+
+    #[test]
+    fn benchbf() {
+        let program = File::open("./brainfuck_programs/bench.bf").unwrap();
+        let options = VMOptions::new(program);
+        let mut vm = test_vm(options);
+        vm.run().unwrap();
+
+        assert_eq!("ZYXWVUTSRQPONMLKJIHGFEDCBA\n", output(&vm));
+    }
+
+    #[test]
+    fn mandel() {
+        let program = File::open("./brainfuck_programs/mandel.bf").unwrap();
+        let options = VMOptions::new(program);
+        let mut vm = test_vm(options);
+        vm.run().unwrap();
+
+        let expected = include_str!("../brainfuck_programs/mandel_output_correct.txt");
+        let expected = dos2unix(expected).to_string();
+
+        assert_eq!(expected, output(&vm));
+    }
+}